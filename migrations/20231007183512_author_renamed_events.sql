@@ -0,0 +1,57 @@
+-- Author::update used to publish author_created on rename, so every author_created
+-- after the first one of its author is really a rename of the previous state.
+with author_event as (
+   select
+      id,
+      payload::jsonb -> 'AuthorCreated' as event,
+      lag(payload::jsonb -> 'AuthorCreated') over (
+         partition by payload::jsonb -> 'AuthorCreated' -> 'id' order by id
+      ) as previous
+   from stored_event
+   where name = 'author_created'
+)
+update stored_event
+set
+   name = 'author_renamed',
+   payload = jsonb_build_object(
+      'AuthorRenamed', jsonb_build_object(
+         'id', author_event.event -> 'id',
+         'previous_first_name', author_event.previous -> 'first_name',
+         'previous_last_name', author_event.previous -> 'last_name',
+         'previous_full_name', author_event.previous -> 'full_name',
+         'first_name', author_event.event -> 'first_name',
+         'last_name', author_event.event -> 'last_name',
+         'full_name', author_event.event -> 'full_name'
+      )
+   )::text
+from author_event
+where stored_event.id = author_event.id and author_event.previous is not null;
+
+-- book_renamed gained previous_name, taken from the preceding event of the same book.
+with book_event as (
+   select
+      id,
+      name,
+      coalesce(payload::jsonb -> 'BookCreated', payload::jsonb -> 'BookRenamed') as event
+   from stored_event
+   where name in ('book_created', 'book_renamed')
+),
+book_event_with_previous as (
+   select
+      id,
+      name,
+      event,
+      lag(event -> 'name') over (partition by event -> 'id' order by id) as previous_name
+   from book_event
+)
+update stored_event
+set payload = jsonb_build_object(
+      'BookRenamed', jsonb_build_object(
+         'id', book_event_with_previous.event -> 'id',
+         'previous_name', book_event_with_previous.previous_name,
+         'name', book_event_with_previous.event -> 'name'
+      )
+   )::text
+from book_event_with_previous
+where stored_event.id = book_event_with_previous.id
+   and book_event_with_previous.name = 'book_renamed';
@@ -0,0 +1,3 @@
+alter table author add column archived boolean not null default false;
+
+alter table book add column archived boolean not null default false;
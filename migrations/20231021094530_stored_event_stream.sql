@@ -0,0 +1,32 @@
+alter table stored_event
+   add column aggregate_type text,
+   add column aggregate_id int,
+   add column sequence int;
+
+update stored_event
+set
+   aggregate_type = stream.aggregate_type,
+   aggregate_id = stream.aggregate_id,
+   sequence = stream.sequence
+from (
+   select
+      id,
+      aggregate_type,
+      aggregate_id,
+      row_number() over (partition by aggregate_type, aggregate_id order by id) as sequence
+   from (
+      select
+         id,
+         case when name like 'book%' then 'book' else 'author' end as aggregate_type,
+         (select (value ->> 'id')::int from jsonb_each(payload::jsonb) limit 1) as aggregate_id
+      from stored_event
+   ) as identified
+) as stream
+where stored_event.id = stream.id;
+
+alter table stored_event
+   alter column aggregate_type set not null,
+   alter column aggregate_id set not null,
+   alter column sequence set not null;
+
+create unique index stored_event_stream_idx on stored_event(aggregate_type, aggregate_id, sequence);
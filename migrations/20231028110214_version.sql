@@ -0,0 +1,15 @@
+alter table author add column version int not null default 0;
+
+alter table book add column version int not null default 0;
+
+update author
+set version = (
+   select count(*) from stored_event
+   where aggregate_type = 'author' and aggregate_id = author.id
+);
+
+update book
+set version = (
+   select count(*) from stored_event
+   where aggregate_type = 'book' and aggregate_id = book.id
+);
@@ -0,0 +1,3 @@
+alter table stored_event add column dispatched_at timestamptz;
+
+create index stored_event_undispatched_idx on stored_event(id) where dispatched_at is null;
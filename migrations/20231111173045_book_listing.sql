@@ -0,0 +1,15 @@
+create table book_listing_author(
+   id int primary key not null,
+   full_name text not null
+);
+
+create table book_listing(
+   id int primary key not null,
+   name text not null,
+   pages_count int not null,
+   author_ids int[] not null,
+   authors text not null,
+   archived boolean not null default false
+);
+
+create index book_listing_author_ids_idx on book_listing using gin(author_ids);
@@ -0,0 +1,4 @@
+create table projection_checkpoint(
+   name text primary key not null,
+   position int not null
+);
@@ -0,0 +1,15 @@
+create table search_document(
+   kind text not null,
+   id int not null,
+   title text not null,
+   document tsvector not null,
+   primary key(kind, id)
+);
+
+create index search_document_idx on search_document using gin(document);
+
+insert into search_document(kind, id, title, document)
+select 'book', id, name, to_tsvector('english', name) from book;
+
+insert into search_document(kind, id, title, document)
+select 'author', id, full_name, to_tsvector('english', full_name) from author;
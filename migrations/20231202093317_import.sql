@@ -0,0 +1,11 @@
+create table import_key(
+   kind text not null,
+   key text not null,
+   id int not null,
+   primary key(kind, key)
+);
+
+create table import_job(
+   name text primary key not null,
+   position int not null
+);
@@ -0,0 +1,3 @@
+alter table book add column isbn text;
+
+create unique index book_isbn on book(isbn);
@@ -0,0 +1,12 @@
+alter table stored_event add column dispatch_attempts int not null default 0;
+alter table stored_event add column dispatch_error text;
+alter table stored_event add column dead_lettered_at timestamptz;
+
+drop index stored_event_undispatched_idx;
+create index stored_event_undispatched_idx on stored_event(id) where dispatched_at is null and dead_lettered_at is null;
+
+create table outbox_delivery(
+   event_id int not null references stored_event(id),
+   handler text not null,
+   primary key(event_id, handler)
+);
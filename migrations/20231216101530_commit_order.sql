@@ -0,0 +1,7 @@
+alter table stored_event add column transaction_id bigint not null default pg_current_xact_id()::text::bigint;
+
+create index stored_event_commit_order_idx on stored_event(transaction_id, id);
+
+alter table projection_checkpoint add column transaction_id bigint not null default 0;
+
+update projection_checkpoint c set transaction_id = e.transaction_id from stored_event e where e.id = c.position;
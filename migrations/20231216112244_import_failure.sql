@@ -0,0 +1,6 @@
+create table import_failure(
+   job text not null,
+   row int not null,
+   errors text[] not null,
+   primary key(job, row)
+);
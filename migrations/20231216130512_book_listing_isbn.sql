@@ -0,0 +1,3 @@
+alter table book_listing add column isbn text;
+
+update book_listing l set isbn = b.isbn from book b where b.id = l.id;
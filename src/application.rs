@@ -1,32 +1,114 @@
 pub mod author;
+pub mod author_listing;
 pub mod book;
-mod book_projector;
+pub mod book_listing;
+pub mod book_projector;
+pub mod catalog;
+pub mod search;
+pub mod search_indexer;
 
-use crate::domain::{DomainEvent, DomainEventPublisher};
+use crate::domain::{Aggregate, DomainError, DomainEvent, DomainEventPublisher, HandlerFailure};
 use async_trait::async_trait;
+use std::{error::Error, fmt};
 
 fn begin<'a>(publisher: &DomainEventPublisher<'a>, event_store: &'a mut dyn EventStore) {
     publisher.subscribe(|e| event_store.append(e));
 }
 
-async fn success(uow: &mut impl UoW) {
-    uow.commit().await
+// Async handlers run before the commit, so a failing handler leaves nothing stored; they
+// take part in the transaction (see AsyncEventHandler). After-commit subscriptions only hear
+// about changes that were actually stored.
+async fn success(
+    publisher: &DomainEventPublisher<'_>,
+    uow: &impl UoW,
+) -> Result<(), ApplicationError> {
+    if let Err(failures) = publisher.dispatch().await {
+        abandon(publisher, uow);
+        return Err(ApplicationError::Handler(failures));
+    }
+    match uow.commit().await {
+        Ok(()) => {
+            publisher.committed();
+            Ok(())
+        }
+        Err(e) => {
+            publisher.discard();
+            Err(e.into())
+        }
+    }
+}
+
+// Drops what a failed use case already staged, in the unit of work and on the publisher.
+fn abandon(publisher: &DomainEventPublisher<'_>, uow: &impl UoW) {
+    uow.rollback();
+    publisher.discard();
+}
+
+// Where an event sits in commit order. Serial ids are taken when a row is inserted, so a
+// transaction can commit a lower id after a higher one was already read; ordering by the
+// writing transaction first, and only reading transactions that have all finished, gives
+// readers a position they never have to look behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Position {
+    pub transaction_id: i64,
+    pub event_id: i32,
 }
 
 #[derive(Debug, Clone)]
 pub struct StoredEvent {
+    id: i32,
+    transaction_id: i64,
+    aggregate_type: String,
+    aggregate_id: i32,
+    sequence: i32,
     name: String,
     payload: String,
 }
 
 impl StoredEvent {
-    pub fn new(name: &str, payload: &str) -> Self {
+    pub fn new(
+        id: i32,
+        transaction_id: i64,
+        aggregate_type: &str,
+        aggregate_id: i32,
+        sequence: i32,
+        name: &str,
+        payload: &str,
+    ) -> Self {
         Self {
+            id,
+            transaction_id,
+            aggregate_type: String::from(aggregate_type),
+            aggregate_id,
+            sequence,
             name: String::from(name),
             payload: String::from(payload),
         }
     }
 
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn position(&self) -> Position {
+        Position {
+            transaction_id: self.transaction_id,
+            event_id: self.id,
+        }
+    }
+
+    pub fn aggregate_type(&self) -> &str {
+        &self.aggregate_type
+    }
+
+    pub fn aggregate_id(&self) -> i32 {
+        self.aggregate_id
+    }
+
+    pub fn sequence(&self) -> i32 {
+        self.sequence
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -34,13 +116,137 @@ impl StoredEvent {
     pub fn playload(&self) -> &str {
         &self.payload
     }
+
+    pub fn domain_event(&self) -> serde_json::Result<DomainEvent> {
+        serde_json::from_str(&self.payload)
+    }
+}
+
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug)]
+pub enum CommitError {
+    Begin(BoxError),
+    Statement {
+        index: usize,
+        sql: String,
+        source: BoxError,
+    },
+    Conflict {
+        index: usize,
+        sql: String,
+    },
+    Commit(BoxError),
+}
+
+impl fmt::Display for CommitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommitError::Begin(e) => write!(f, "failed to begin transaction: {}", e),
+            CommitError::Statement { index, sql, source } => {
+                write!(f, "statement #{} `{}` failed: {}", index, sql, source)
+            }
+            CommitError::Conflict { index, sql } => {
+                write!(f, "statement #{} `{}` hit a concurrent change", index, sql)
+            }
+            CommitError::Commit(e) => write!(f, "failed to commit transaction: {}", e),
+        }
+    }
 }
 
+impl Error for CommitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CommitError::Begin(e) | CommitError::Commit(e) => Some(e.as_ref()),
+            CommitError::Statement { source, .. } => Some(source.as_ref()),
+            CommitError::Conflict { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ApplicationError {
+    NotFound(i32),
+    Domain(Vec<DomainError>),
+    InvalidQuery(String),
+    Handler(Vec<HandlerFailure>),
+    Conflict(CommitError),
+    Commit(CommitError),
+    Repository(BoxError),
+}
+
+impl fmt::Display for ApplicationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplicationError::NotFound(id) => write!(f, "aggregate {} not found", id),
+            ApplicationError::Domain(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", errors.join("; "))
+            }
+            ApplicationError::InvalidQuery(e) => write!(f, "{}", e),
+            ApplicationError::Handler(failures) => {
+                let failures: Vec<String> = failures.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", failures.join("; "))
+            }
+            ApplicationError::Conflict(e) | ApplicationError::Commit(e) => write!(f, "{}", e),
+            ApplicationError::Repository(e) => write!(f, "failed to load aggregate: {}", e),
+        }
+    }
+}
+
+impl Error for ApplicationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ApplicationError::NotFound(_)
+            | ApplicationError::Domain(_)
+            | ApplicationError::InvalidQuery(_) => None,
+            ApplicationError::Handler(failures) => failures.first().map(|e| e as &dyn Error),
+            ApplicationError::Conflict(e) | ApplicationError::Commit(e) => Some(e),
+            ApplicationError::Repository(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+impl From<Vec<DomainError>> for ApplicationError {
+    fn from(errors: Vec<DomainError>) -> Self {
+        ApplicationError::Domain(errors)
+    }
+}
+
+impl From<BoxError> for ApplicationError {
+    fn from(e: BoxError) -> Self {
+        ApplicationError::Repository(e)
+    }
+}
+
+impl From<CommitError> for ApplicationError {
+    fn from(e: CommitError) -> Self {
+        match e {
+            CommitError::Conflict { .. } => ApplicationError::Conflict(e),
+            _ => ApplicationError::Commit(e),
+        }
+    }
+}
+
+/// Pending changes are discarded by both `commit` and `rollback`, whatever the outcome,
+/// so a unit of work can be reused for the next batch.
 #[async_trait]
 pub trait UoW {
-    async fn commit(&self);
+    async fn commit(&self) -> Result<(), CommitError>;
+    fn rollback(&self);
 }
 
+#[async_trait]
+pub trait EventHandler: Send + Sync {
+    async fn handle(&self, event: &StoredEvent) -> Result<(), BoxError>;
+}
+
+#[async_trait]
 pub trait EventStore: Send + Sync {
     fn append(&mut self, domain_event: &DomainEvent);
+    async fn load_stream(
+        &self,
+        aggregate: Aggregate,
+        id: i32,
+    ) -> Result<Vec<StoredEvent>, BoxError>;
 }
@@ -1,27 +1,248 @@
 use super::*;
-use crate::domain::author::{Author, AuthorRepository};
+use crate::domain::{
+    author::{Author, AuthorRepository},
+    book::BookRepository,
+};
 
-pub async fn create(
+pub async fn create<'a>(
     first_name: &str,
     last_name: &str,
-    author_repository: &mut impl AuthorRepository<'_, '_>,
-    event_store: &mut impl EventStore,
-    uow: &mut impl UoW,
-) {
-    let publisher = DomainEventPublisher::new();
-    begin(&publisher, event_store);
-    book_projector::create(&publisher);
+    publisher: &DomainEventPublisher<'a>,
+    author_repository: &impl AuthorRepository<'_, '_>,
+    event_store: &'a mut impl EventStore,
+    uow: &impl UoW,
+) -> Result<i32, ApplicationError> {
+    begin(publisher, event_store);
 
     let id = author_repository.next_identity().await;
-    let author = Author::new(id, first_name, last_name, &publisher);
+    let author = Author::new(id, first_name, last_name, publisher)?;
     author_repository.create(&author);
 
-    success(uow).await;
+    success(publisher, uow).await?;
+    Ok(id)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn update<'a, 'b>(
+    id: i32,
+    first_name: &str,
+    last_name: &str,
+    publisher: &'b DomainEventPublisher<'a>,
+    author_repository: &impl AuthorRepository<'b, 'a>,
+    event_store: &'a mut impl EventStore,
+    uow: &impl UoW,
+) -> Result<(), ApplicationError> {
+    begin(publisher, event_store);
+
+    let mut author = author_repository
+        .by_id(id)
+        .await?
+        .ok_or(ApplicationError::NotFound(id))?;
+    author.update(first_name, last_name)?;
+    author_repository.update(&author);
+
+    success(publisher, uow).await
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn archive<'a, 'b>(
+    id: i32,
+    reassign_to: Option<i32>,
+    publisher: &'b DomainEventPublisher<'a>,
+    author_repository: &impl AuthorRepository<'b, 'a>,
+    book_repository: &impl BookRepository<'b, 'a>,
+    event_store: &'a mut impl EventStore,
+    uow: &impl UoW,
+) -> Result<(), ApplicationError> {
+    begin(publisher, event_store);
+
+    let mut author = author_repository
+        .by_id(id)
+        .await?
+        .ok_or(ApplicationError::NotFound(id))?;
+    let reassign_to = match reassign_to {
+        Some(reassign_to) => Some(
+            author_repository
+                .by_id(reassign_to)
+                .await?
+                .ok_or(ApplicationError::NotFound(reassign_to))?,
+        ),
+        None => None,
+    };
+
+    let mut books = book_repository.active_by_author(id).await?;
+    let book_ids: Vec<i32> = books.iter().map(|b| b.id()).collect();
+    author.archive(&book_ids, reassign_to.as_ref())?;
+
+    if let Some(reassign_to) = &reassign_to {
+        for book in books.iter_mut() {
+            if let Err(e) = book.reassign_author(id, reassign_to.id()) {
+                abandon(publisher, uow);
+                return Err(e.into());
+            }
+            book_repository.update(book);
+        }
+    }
+    author_repository.archive(&author);
+
+    success(publisher, uow).await
+}
+
+pub async fn restore<'a, 'b>(
+    id: i32,
+    publisher: &'b DomainEventPublisher<'a>,
+    author_repository: &impl AuthorRepository<'b, 'a>,
+    event_store: &'a mut impl EventStore,
+    uow: &impl UoW,
+) -> Result<(), ApplicationError> {
+    begin(publisher, event_store);
+
+    let mut author = author_repository
+        .by_id(id)
+        .await?
+        .ok_or(ApplicationError::NotFound(id))?;
+    author.restore();
+    author_repository.restore(&author);
+
+    success(publisher, uow).await
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
+    use crate::{
+        domain::DomainError,
+        infrastructure::{author::DbAuthorRepository, book::DbBookRepository, DbEventStore, DbUoW},
+    };
+    use sqlx::{PgPool, Row};
+
+    #[sqlx::test(fixtures(path = "../infrastructure/fixtures", scripts("author")))]
+    async fn update(pool: PgPool) {
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = DbAuthorRepository::new(&uow, &publisher);
+
+        super::update(
+            1,
+            "f1",
+            "O'Brien",
+            &publisher,
+            &repo,
+            &mut event_store,
+            &uow,
+        )
+        .await
+        .unwrap();
+
+        let row = sqlx::query("select * from author where id = 1")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(row.get::<&str, _>("full_name"), "f1 O'Brien");
+
+        let events = sqlx::query("select * from stored_event")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+    }
+
+    #[sqlx::test(fixtures(path = "../infrastructure/fixtures", scripts("author")))]
+    async fn update_not_found(pool: PgPool) {
+        let uow = DbUoW::new(pool);
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = DbAuthorRepository::new(&uow, &publisher);
+
+        let result = super::update(42, "f", "l", &publisher, &repo, &mut event_store, &uow).await;
+
+        assert!(matches!(result, Err(ApplicationError::NotFound(42))));
+    }
+
+    #[sqlx::test(fixtures(path = "../infrastructure/fixtures", scripts("book")))]
+    async fn archive_with_active_books(pool: PgPool) {
+        let uow = DbUoW::new(pool);
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let author_repo = DbAuthorRepository::new(&uow, &publisher);
+        let book_repo = DbBookRepository::new(&uow, &publisher);
+
+        let result = super::archive(
+            1,
+            None,
+            &publisher,
+            &author_repo,
+            &book_repo,
+            &mut event_store,
+            &uow,
+        )
+        .await;
+
+        match result {
+            Err(ApplicationError::Domain(errors)) => {
+                assert_eq!(errors, vec![DomainError::AuthorHasActiveBooks(vec![1])])
+            }
+            _ => panic!("archive must fail"),
+        }
+        assert!(!author_repo.by_id(1).await.unwrap().unwrap().archived());
+    }
+
+    #[sqlx::test(fixtures(path = "../infrastructure/fixtures", scripts("book")))]
+    async fn archive_with_reassignment(pool: PgPool) {
+        let uow = DbUoW::new(pool);
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let author_repo = DbAuthorRepository::new(&uow, &publisher);
+        let book_repo = DbBookRepository::new(&uow, &publisher);
+
+        super::archive(
+            1,
+            Some(2),
+            &publisher,
+            &author_repo,
+            &book_repo,
+            &mut event_store,
+            &uow,
+        )
+        .await
+        .unwrap();
+
+        assert!(author_repo.by_id(1).await.unwrap().unwrap().archived());
+        assert_eq!(
+            book_repo.by_id(1).await.unwrap().unwrap().authors(),
+            vec![2]
+        );
+    }
+
+    #[sqlx::test(fixtures(path = "../infrastructure/fixtures", scripts("book")))]
+    async fn failed_reassignment_discards_events(pool: PgPool) {
+        sqlx::query("update book set pages_count = 0 where id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let committed = std::sync::Mutex::new(Vec::new());
+        let uow = DbUoW::new(pool);
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        publisher.subscribe_after_commit(|e| committed.lock().unwrap().push(e.domain_event_name()));
+        let author_repo = DbAuthorRepository::new(&uow, &publisher);
+        let book_repo = DbBookRepository::new(&uow, &publisher);
+
+        let result = super::archive(
+            1,
+            Some(2),
+            &publisher,
+            &author_repo,
+            &book_repo,
+            &mut event_store,
+            &uow,
+        )
+        .await;
+        assert!(matches!(result, Err(ApplicationError::Domain(_))));
 
-    #[test]
-    fn create() {}
+        publisher.committed();
+        assert!(committed.lock().unwrap().is_empty());
+        assert!(!author_repo.by_id(1).await.unwrap().unwrap().archived());
+    }
 }
@@ -0,0 +1,31 @@
+use super::*;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuthorListItem {
+    pub id: i32,
+    pub first_name: String,
+    pub last_name: String,
+    pub full_name: String,
+    pub archived: bool,
+}
+
+#[async_trait]
+pub trait AuthorListing: Send + Sync {
+    async fn list(&self) -> Vec<AuthorListItem>;
+    async fn by_id(&self, id: i32) -> Option<AuthorListItem>;
+}
+
+pub async fn list(author_listing: &impl AuthorListing) -> Vec<AuthorListItem> {
+    author_listing.list().await
+}
+
+pub async fn get(
+    id: i32,
+    author_listing: &impl AuthorListing,
+) -> Result<AuthorListItem, ApplicationError> {
+    author_listing
+        .by_id(id)
+        .await
+        .ok_or(ApplicationError::NotFound(id))
+}
@@ -1,20 +1,542 @@
 use super::*;
-use crate::domain::book::{Book, BookRepository};
+use crate::domain::{
+    author::AuthorRepository,
+    book::{Book, BookRepository},
+    isbn::Isbn,
+};
 
-pub async fn create<'a>(
+#[allow(clippy::too_many_arguments)]
+pub async fn create<'a, 'b>(
     name: &str,
     pages_count: i32,
     authors: Vec<i32>,
-    book_repository: &mut impl BookRepository<'_, '_>,
+    isbn: Option<&str>,
+    publisher: &'b DomainEventPublisher<'a>,
+    book_repository: &impl BookRepository<'_, '_>,
+    author_repository: &impl AuthorRepository<'b, 'a>,
     event_store: &'a mut impl EventStore,
-    uow: &mut impl UoW,
-) {
-    let publisher = DomainEventPublisher::new();
-    begin(&publisher, event_store);
+    uow: &impl UoW,
+) -> Result<i32, ApplicationError> {
+    begin(publisher, event_store);
+
+    let mut errors = Book::validate(name, pages_count, &authors)
+        .err()
+        .unwrap_or_default();
+    errors.extend(check_authors(&authors, &[], author_repository).await?);
+    let isbn = check_isbn(isbn, None, book_repository)
+        .await
+        .unwrap_or_else(|e| {
+            errors.extend(e);
+            None
+        });
+    if !errors.is_empty() {
+        return Err(errors.into());
+    }
 
     let id = book_repository.next_identity().await;
-    let book = Book::new(id, name, pages_count, authors, &publisher);
+    let book = Book::new(id, name, pages_count, authors, isbn, publisher)?;
     book_repository.create(&book);
 
-    success(uow).await;
+    success(publisher, uow).await?;
+    Ok(id)
+}
+
+// `isbn` is left as it is when `None`, and cleared when `Some(None)`.
+#[allow(clippy::too_many_arguments)]
+pub async fn update<'a, 'b>(
+    id: i32,
+    name: &str,
+    pages_count: i32,
+    authors: Vec<i32>,
+    isbn: Option<Option<&str>>,
+    publisher: &'b DomainEventPublisher<'a>,
+    book_repository: &impl BookRepository<'b, 'a>,
+    author_repository: &impl AuthorRepository<'b, 'a>,
+    event_store: &'a mut impl EventStore,
+    uow: &impl UoW,
+) -> Result<(), ApplicationError> {
+    begin(publisher, event_store);
+
+    let mut book = book_repository
+        .by_id(id)
+        .await?
+        .ok_or(ApplicationError::NotFound(id))?;
+    let mut errors = book
+        .validate_update(name, pages_count, &authors)
+        .err()
+        .unwrap_or_default();
+    errors.extend(check_authors(&authors, book.authors(), author_repository).await?);
+    let isbn = match isbn {
+        Some(isbn) => check_isbn(isbn, Some(id), book_repository)
+            .await
+            .map(Some)
+            .unwrap_or_else(|e| {
+                errors.extend(e);
+                None
+            }),
+        None => None,
+    };
+    if !errors.is_empty() {
+        return Err(errors.into());
+    }
+
+    book.update(name, pages_count, authors)?;
+    if let Some(isbn) = isbn {
+        book.change_isbn(isbn)?;
+    }
+    book_repository.update(&book);
+
+    success(publisher, uow).await
+}
+
+pub async fn archive<'a, 'b>(
+    id: i32,
+    publisher: &'b DomainEventPublisher<'a>,
+    book_repository: &impl BookRepository<'b, 'a>,
+    event_store: &'a mut impl EventStore,
+    uow: &impl UoW,
+) -> Result<(), ApplicationError> {
+    begin(publisher, event_store);
+
+    let mut book = book_repository
+        .by_id(id)
+        .await?
+        .ok_or(ApplicationError::NotFound(id))?;
+    book.archive();
+    book_repository.archive(&book);
+
+    success(publisher, uow).await
+}
+
+pub async fn restore<'a, 'b>(
+    id: i32,
+    publisher: &'b DomainEventPublisher<'a>,
+    book_repository: &impl BookRepository<'b, 'a>,
+    event_store: &'a mut impl EventStore,
+    uow: &impl UoW,
+) -> Result<(), ApplicationError> {
+    begin(publisher, event_store);
+
+    let mut book = book_repository
+        .by_id(id)
+        .await?
+        .ok_or(ApplicationError::NotFound(id))?;
+    book.restore();
+    book_repository.restore(&book);
+
+    success(publisher, uow).await
+}
+
+// The unique index on book.isbn still guards against two books taking the same ISBN
+// concurrently, which surfaces as a conflict; this check makes the common case a domain
+// error.
+async fn check_isbn(
+    isbn: Option<&str>,
+    book_id: Option<i32>,
+    book_repository: &impl BookRepository<'_, '_>,
+) -> Result<Option<Isbn>, Vec<DomainError>> {
+    let Some(isbn) = isbn else {
+        return Ok(None);
+    };
+    let isbn = Isbn::parse(isbn).map_err(|e| vec![e])?;
+    match book_repository.id_by_isbn(&isbn).await {
+        Some(other) if Some(other) != book_id => {
+            Err(vec![DomainError::DuplicateIsbn(isbn.hyphenated())])
+        }
+        _ => Ok(Some(isbn)),
+    }
+}
+
+// Authors already on the book may have been archived since; only the ones being added have
+// to be active.
+async fn check_authors<'a: 'b, 'b>(
+    authors: &[i32],
+    current: &[i32],
+    author_repository: &impl AuthorRepository<'b, 'a>,
+) -> Result<Vec<DomainError>, BoxError> {
+    let mut unknown = Vec::new();
+    let mut errors = Vec::new();
+    for (i, id) in authors.iter().enumerate() {
+        if authors[..i].contains(id) || current.contains(id) {
+            continue;
+        }
+        match author_repository.by_id(*id).await? {
+            None => unknown.push(*id),
+            Some(author) if author.archived() => errors.push(DomainError::AuthorArchived(*id)),
+            Some(_) => {}
+        }
+    }
+    if !unknown.is_empty() {
+        errors.insert(0, DomainError::UnknownAuthors(unknown));
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        domain::AsyncEventHandler,
+        infrastructure::{author::DbAuthorRepository, book::DbBookRepository, DbEventStore, DbUoW},
+    };
+    use sqlx::{PgPool, Row};
+
+    #[sqlx::test(fixtures(path = "../infrastructure/fixtures", scripts("book")))]
+    async fn update(pool: PgPool) {
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = DbBookRepository::new(&uow, &publisher);
+        let authors = DbAuthorRepository::new(&uow, &publisher);
+
+        super::update(
+            1,
+            "book1-renamed",
+            200,
+            vec![2],
+            None,
+            &publisher,
+            &repo,
+            &authors,
+            &mut event_store,
+            &uow,
+        )
+        .await
+        .unwrap();
+
+        let book = repo.by_id(1).await.unwrap().unwrap();
+        assert_eq!(book.name(), "book1-renamed");
+        assert_eq!(book.pages_count(), 200);
+        assert_eq!(book.authors(), vec![2]);
+
+        let events = sqlx::query("select * from stored_event order by id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let names: Vec<&str> = events.iter().map(|e| e.get("name")).collect();
+        assert_eq!(
+            names,
+            vec![
+                "book_renamed",
+                "book_pages_count_changed",
+                "book_author_removed"
+            ]
+        );
+    }
+
+    #[sqlx::test(fixtures(path = "../infrastructure/fixtures", scripts("book")))]
+    async fn update_not_found(pool: PgPool) {
+        let uow = DbUoW::new(pool);
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = DbBookRepository::new(&uow, &publisher);
+        let authors = DbAuthorRepository::new(&uow, &publisher);
+
+        let result = super::update(
+            42,
+            "book42",
+            10,
+            vec![1],
+            None,
+            &publisher,
+            &repo,
+            &authors,
+            &mut event_store,
+            &uow,
+        )
+        .await;
+
+        assert!(matches!(result, Err(ApplicationError::NotFound(42))));
+    }
+
+    struct Rejecting;
+
+    #[async_trait]
+    impl AsyncEventHandler for Rejecting {
+        async fn handle(&self, _: &DomainEvent) -> Result<(), BoxError> {
+            Err("rejected".into())
+        }
+    }
+
+    #[sqlx::test(fixtures(path = "../infrastructure/fixtures", scripts("book")))]
+    async fn update_handler_failure(pool: PgPool) {
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        publisher.subscribe_async(Rejecting);
+        let repo = DbBookRepository::new(&uow, &publisher);
+        let authors = DbAuthorRepository::new(&uow, &publisher);
+
+        let result = super::update(
+            1,
+            "book1-renamed",
+            200,
+            vec![2],
+            None,
+            &publisher,
+            &repo,
+            &authors,
+            &mut event_store,
+            &uow,
+        )
+        .await;
+
+        assert!(matches!(result, Err(ApplicationError::Handler(f)) if f.len() == 1));
+        assert_eq!(repo.by_id(1).await.unwrap().unwrap().name(), "book1");
+        let events: (i64,) = sqlx::query_as("select count(*) from stored_event")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(events.0, 0);
+    }
+
+    struct BumpVersion(PgPool);
+
+    #[async_trait]
+    impl AsyncEventHandler for BumpVersion {
+        async fn handle(&self, _: &DomainEvent) -> Result<(), BoxError> {
+            sqlx::query("update book set version = version + 1 where id = 1")
+                .execute(&self.0)
+                .await?;
+            Ok(())
+        }
+    }
+
+    #[sqlx::test(fixtures(path = "../infrastructure/fixtures", scripts("book")))]
+    async fn after_commit_only_on_success(pool: PgPool) {
+        let committed = std::sync::Mutex::new(Vec::new());
+        for conflict in [true, false] {
+            let uow = DbUoW::new(pool.clone());
+            let mut event_store = DbEventStore::new(&uow);
+            let publisher = DomainEventPublisher::new();
+            publisher
+                .subscribe_after_commit(|e| committed.lock().unwrap().push(e.domain_event_name()));
+            if conflict {
+                publisher.subscribe_async(BumpVersion(pool.clone()));
+            }
+            let repo = DbBookRepository::new(&uow, &publisher);
+            let authors = DbAuthorRepository::new(&uow, &publisher);
+
+            let result = super::update(
+                1,
+                "book1-renamed",
+                100,
+                vec![1, 2],
+                None,
+                &publisher,
+                &repo,
+                &authors,
+                &mut event_store,
+                &uow,
+            )
+            .await;
+
+            assert_eq!(
+                matches!(result, Err(ApplicationError::Conflict(_))),
+                conflict
+            );
+        }
+
+        assert_eq!(*committed.lock().unwrap(), vec!["book_renamed"]);
+    }
+
+    #[sqlx::test(fixtures(path = "../infrastructure/fixtures", scripts("book")))]
+    async fn isbn(pool: PgPool) {
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = DbBookRepository::new(&uow, &publisher);
+        let authors = DbAuthorRepository::new(&uow, &publisher);
+
+        super::update(
+            1,
+            "book1",
+            100,
+            vec![1, 2],
+            Some(Some("0-306-40615-2")),
+            &publisher,
+            &repo,
+            &authors,
+            &mut event_store,
+            &uow,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            repo.by_id(1)
+                .await
+                .unwrap()
+                .unwrap()
+                .isbn()
+                .unwrap()
+                .as_str(),
+            "9780306406157"
+        );
+
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = DbBookRepository::new(&uow, &publisher);
+        let authors = DbAuthorRepository::new(&uow, &publisher);
+
+        super::update(
+            1,
+            "book1",
+            100,
+            vec![1, 2],
+            None,
+            &publisher,
+            &repo,
+            &authors,
+            &mut event_store,
+            &uow,
+        )
+        .await
+        .unwrap();
+        assert!(repo.by_id(1).await.unwrap().unwrap().isbn().is_some());
+
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = DbBookRepository::new(&uow, &publisher);
+        let authors = DbAuthorRepository::new(&uow, &publisher);
+
+        let result = super::create(
+            "book2",
+            100,
+            vec![1],
+            Some("978-0-306-40615-7"),
+            &publisher,
+            &repo,
+            &authors,
+            &mut event_store,
+            &uow,
+        )
+        .await;
+        match result {
+            Err(ApplicationError::Domain(errors)) => assert_eq!(
+                errors,
+                vec![DomainError::DuplicateIsbn(String::from(
+                    "978-0-306-40615-7"
+                ))]
+            ),
+            _ => panic!("expected domain errors, got {:?}", result),
+        }
+
+        let events: Vec<(String, String)> =
+            sqlx::query_as("select name, payload from stored_event order by id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, "book_isbn_changed");
+        assert!(events[0].1.contains("\"isbn\":\"9780306406157\""));
+    }
+
+    #[sqlx::test(fixtures(path = "../infrastructure/fixtures", scripts("book")))]
+    async fn create_with_invalid_authors(pool: PgPool) {
+        sqlx::query("update author set archived = true where id = 2")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = DbBookRepository::new(&uow, &publisher);
+        let authors = DbAuthorRepository::new(&uow, &publisher);
+
+        let result = super::create(
+            "",
+            0,
+            vec![1, 7, 2, 9, 7],
+            None,
+            &publisher,
+            &repo,
+            &authors,
+            &mut event_store,
+            &uow,
+        )
+        .await;
+
+        match result {
+            Err(ApplicationError::Domain(errors)) => assert_eq!(
+                errors,
+                vec![
+                    DomainError::EmptyBookName,
+                    DomainError::NonPositivePagesCount(0),
+                    DomainError::DuplicateBookAuthors(vec![7]),
+                    DomainError::UnknownAuthors(vec![7, 9]),
+                    DomainError::AuthorArchived(2)
+                ]
+            ),
+            _ => panic!("expected domain errors, got {:?}", result),
+        }
+        let books: (i64,) = sqlx::query_as("select count(*) from book")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(books.0, 1);
+    }
+
+    #[sqlx::test(fixtures(path = "../infrastructure/fixtures", scripts("book")))]
+    async fn update_keeps_archived_authors(pool: PgPool) {
+        sqlx::query("update author set archived = true where id = 2")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("insert into author(id, first_name, last_name, full_name, archived) values (3, 'f3', 'l3', 'f3 l3', true)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = DbBookRepository::new(&uow, &publisher);
+        let authors = DbAuthorRepository::new(&uow, &publisher);
+
+        let result = super::update(
+            1,
+            "book1",
+            100,
+            vec![1, 2, 3],
+            None,
+            &publisher,
+            &repo,
+            &authors,
+            &mut event_store,
+            &uow,
+        )
+        .await;
+        match result {
+            Err(ApplicationError::Domain(errors)) => {
+                assert_eq!(errors, vec![DomainError::AuthorArchived(3)])
+            }
+            _ => panic!("expected domain errors, got {:?}", result),
+        }
+
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = DbBookRepository::new(&uow, &publisher);
+        let authors = DbAuthorRepository::new(&uow, &publisher);
+
+        super::update(
+            1,
+            "book1-renamed",
+            100,
+            vec![1, 2],
+            None,
+            &publisher,
+            &repo,
+            &authors,
+            &mut event_store,
+            &uow,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            repo.by_id(1).await.unwrap().unwrap().name(),
+            "book1-renamed"
+        );
+    }
 }
@@ -0,0 +1,48 @@
+use super::*;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BookListItem {
+    pub id: i32,
+    pub name: String,
+    pub pages_count: i32,
+    pub author_ids: Vec<i32>,
+    pub authors: String,
+    pub isbn: Option<String>,
+}
+
+#[async_trait]
+pub trait BookListing: Send + Sync {
+    async fn add_author(&self, id: i32, full_name: &str) -> Result<(), BoxError>;
+    async fn rename_author(&self, id: i32, full_name: &str) -> Result<(), BoxError>;
+    async fn add_book(
+        &self,
+        id: i32,
+        name: &str,
+        pages_count: i32,
+        author_ids: &[i32],
+        isbn: Option<&str>,
+    ) -> Result<(), BoxError>;
+    async fn rename_book(&self, id: i32, name: &str) -> Result<(), BoxError>;
+    async fn change_pages_count(&self, id: i32, pages_count: i32) -> Result<(), BoxError>;
+    async fn change_isbn(&self, id: i32, isbn: Option<&str>) -> Result<(), BoxError>;
+    async fn add_book_author(&self, id: i32, author_id: i32) -> Result<(), BoxError>;
+    async fn remove_book_author(&self, id: i32, author_id: i32) -> Result<(), BoxError>;
+    async fn archive_book(&self, id: i32, archived: bool) -> Result<(), BoxError>;
+    async fn list(&self) -> Vec<BookListItem>;
+    async fn by_id(&self, id: i32) -> Option<BookListItem>;
+}
+
+pub async fn list(book_listing: &impl BookListing) -> Vec<BookListItem> {
+    book_listing.list().await
+}
+
+pub async fn get(
+    id: i32,
+    book_listing: &impl BookListing,
+) -> Result<BookListItem, ApplicationError> {
+    book_listing
+        .by_id(id)
+        .await
+        .ok_or(ApplicationError::NotFound(id))
+}
@@ -1,20 +1,103 @@
+use super::{book_listing::BookListing, BoxError, EventHandler, StoredEvent};
 use crate::domain::{
-    book::{BookCreated, BookRenamed},
-    DomainEventPublisher,
+    author::{AuthorCreated, AuthorRenamed},
+    book::{
+        BookArchived, BookAuthorAdded, BookAuthorRemoved, BookCreated, BookIsbnChanged,
+        BookPagesCountChanged, BookRenamed, BookRestored,
+    },
+    isbn::Isbn,
+    DomainEvent,
 };
+use async_trait::async_trait;
 
-pub fn create(publisher: &DomainEventPublisher) {
-    publisher.subscribe(|e| match e {
-        crate::domain::DomainEvent::BookCreated(e) => on_book_created(e),
-        crate::domain::DomainEvent::BookRenamed(e) => on_book_renamed(e),
-        _ => {}
-    })
+pub struct BookProjector<L> {
+    book_listing: L,
 }
 
-fn on_book_created(e: &BookCreated) {
-    println!("{:?}", e);
+impl<L: BookListing> BookProjector<L> {
+    pub fn new(book_listing: L) -> Self {
+        Self { book_listing }
+    }
 }
 
-fn on_book_renamed(e: &BookRenamed) {
-    println!("{:?}", e);
+#[async_trait]
+impl<L: BookListing> EventHandler for BookProjector<L> {
+    async fn handle(&self, event: &StoredEvent) -> Result<(), BoxError> {
+        let listing = &self.book_listing;
+        match event.domain_event()? {
+            DomainEvent::AuthorCreated(e) => on_author_created(e, listing).await,
+            DomainEvent::AuthorRenamed(e) => on_author_renamed(e, listing).await,
+            DomainEvent::BookCreated(e) => on_book_created(e, listing).await,
+            DomainEvent::BookRenamed(e) => on_book_renamed(e, listing).await,
+            DomainEvent::BookPagesCountChanged(e) => on_book_pages_count_changed(e, listing).await,
+            DomainEvent::BookIsbnChanged(e) => on_book_isbn_changed(e, listing).await,
+            DomainEvent::BookAuthorAdded(e) => on_book_author_added(e, listing).await,
+            DomainEvent::BookAuthorRemoved(e) => on_book_author_removed(e, listing).await,
+            DomainEvent::BookArchived(e) => on_book_archived(e, listing).await,
+            DomainEvent::BookRestored(e) => on_book_restored(e, listing).await,
+            _ => Ok(()),
+        }
+    }
+}
+
+async fn on_author_created(e: AuthorCreated, listing: &impl BookListing) -> Result<(), BoxError> {
+    listing.add_author(e.id, &e.full_name).await
+}
+
+async fn on_author_renamed(e: AuthorRenamed, listing: &impl BookListing) -> Result<(), BoxError> {
+    listing.rename_author(e.id, &e.full_name).await
+}
+
+async fn on_book_created(e: BookCreated, listing: &impl BookListing) -> Result<(), BoxError> {
+    listing
+        .add_book(
+            e.id,
+            &e.name,
+            e.pages_count,
+            &e.authors,
+            e.isbn.as_ref().map(Isbn::as_str),
+        )
+        .await
+}
+
+async fn on_book_renamed(e: BookRenamed, listing: &impl BookListing) -> Result<(), BoxError> {
+    listing.rename_book(e.id, &e.name).await
+}
+
+async fn on_book_pages_count_changed(
+    e: BookPagesCountChanged,
+    listing: &impl BookListing,
+) -> Result<(), BoxError> {
+    listing.change_pages_count(e.id, e.pages_count).await
+}
+
+async fn on_book_isbn_changed(
+    e: BookIsbnChanged,
+    listing: &impl BookListing,
+) -> Result<(), BoxError> {
+    listing
+        .change_isbn(e.id, e.isbn.as_ref().map(Isbn::as_str))
+        .await
+}
+
+async fn on_book_author_added(
+    e: BookAuthorAdded,
+    listing: &impl BookListing,
+) -> Result<(), BoxError> {
+    listing.add_book_author(e.id, e.author_id).await
+}
+
+async fn on_book_author_removed(
+    e: BookAuthorRemoved,
+    listing: &impl BookListing,
+) -> Result<(), BoxError> {
+    listing.remove_book_author(e.id, e.author_id).await
+}
+
+async fn on_book_archived(e: BookArchived, listing: &impl BookListing) -> Result<(), BoxError> {
+    listing.archive_book(e.id, true).await
+}
+
+async fn on_book_restored(e: BookRestored, listing: &impl BookListing) -> Result<(), BoxError> {
+    listing.archive_book(e.id, false).await
 }
@@ -0,0 +1,182 @@
+use super::*;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sort {
+    #[default]
+    Id,
+    Name,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BookFilter {
+    pub author_id: Option<i32>,
+    pub name: Option<String>,
+    pub min_pages_count: Option<i32>,
+    pub max_pages_count: Option<i32>,
+    pub include_archived: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuthorFilter {
+    pub name: Option<String>,
+    pub include_archived: bool,
+}
+
+// Keyset position of the last item on a page: the sort key followed by the id, which breaks
+// ties so that items sharing a name are neither skipped nor repeated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cursor {
+    Id(i32),
+    Name(String, i32),
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_string(self).expect("cursor serialized");
+        json.bytes().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn decode(cursor: &str) -> Option<Cursor> {
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(cursor.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    fn matches(&self, sort: Sort) -> bool {
+        matches!(
+            (self, sort),
+            (Cursor::Id(_), Sort::Id) | (Cursor::Name(..), Sort::Name)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BookSummary {
+    pub id: i32,
+    pub name: String,
+    pub pages_count: i32,
+    pub authors: Vec<i32>,
+    pub isbn: Option<String>,
+    pub archived: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuthorSummary {
+    pub id: i32,
+    pub first_name: String,
+    pub last_name: String,
+    pub full_name: String,
+    pub archived: bool,
+}
+
+#[async_trait]
+pub trait Catalog: Send + Sync {
+    async fn book(&self, id: i32) -> Option<BookSummary>;
+    async fn books(
+        &self,
+        filter: &BookFilter,
+        sort: Sort,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> Vec<BookSummary>;
+    async fn authors(
+        &self,
+        filter: &AuthorFilter,
+        sort: Sort,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> Vec<AuthorSummary>;
+}
+
+pub async fn book(id: i32, catalog: &impl Catalog) -> Result<BookSummary, ApplicationError> {
+    catalog.book(id).await.ok_or(ApplicationError::NotFound(id))
+}
+
+pub async fn books(
+    filter: &BookFilter,
+    sort: Sort,
+    after: Option<&str>,
+    limit: i64,
+    catalog: &impl Catalog,
+) -> Result<Page<BookSummary>, ApplicationError> {
+    let after = cursor(after, sort)?;
+    let limit = limit.clamp(1, MAX_PAGE_SIZE);
+
+    let mut items = catalog.books(filter, sort, after.as_ref(), limit + 1).await;
+    Ok(page(&mut items, limit, |b| match sort {
+        Sort::Id => Cursor::Id(b.id),
+        Sort::Name => Cursor::Name(b.name.clone(), b.id),
+    }))
+}
+
+pub async fn authors(
+    filter: &AuthorFilter,
+    sort: Sort,
+    after: Option<&str>,
+    limit: i64,
+    catalog: &impl Catalog,
+) -> Result<Page<AuthorSummary>, ApplicationError> {
+    let after = cursor(after, sort)?;
+    let limit = limit.clamp(1, MAX_PAGE_SIZE);
+
+    let mut items = catalog
+        .authors(filter, sort, after.as_ref(), limit + 1)
+        .await;
+    Ok(page(&mut items, limit, |a| match sort {
+        Sort::Id => Cursor::Id(a.id),
+        Sort::Name => Cursor::Name(a.full_name.clone(), a.id),
+    }))
+}
+
+fn cursor(after: Option<&str>, sort: Sort) -> Result<Option<Cursor>, ApplicationError> {
+    match after {
+        None => Ok(None),
+        Some(after) => match Cursor::decode(after) {
+            Some(cursor) if cursor.matches(sort) => Ok(Some(cursor)),
+            _ => Err(ApplicationError::InvalidQuery(format!(
+                "invalid cursor {}",
+                after
+            ))),
+        },
+    }
+}
+
+// One row more than `limit` is fetched to tell whether another page follows.
+fn page<T: Clone>(items: &mut Vec<T>, limit: i64, cursor: impl Fn(&T) -> Cursor) -> Page<T> {
+    let more = items.len() as i64 > limit;
+    items.truncate(limit as usize);
+    Page {
+        next: if more {
+            items.last().map(|item| cursor(item).encode())
+        } else {
+            None
+        },
+        items: std::mem::take(items),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor::Name(String::from("Ďábel's book"), 7);
+
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        assert_eq!(Cursor::decode("zz"), None);
+        assert_eq!(Cursor::decode("7"), None);
+    }
+}
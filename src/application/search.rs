@@ -0,0 +1,68 @@
+use super::*;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchKind {
+    Book,
+    Author,
+}
+
+impl SearchKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SearchKind::Book => "book",
+            SearchKind::Author => "author",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchHit {
+    pub kind: SearchKind,
+    pub id: i32,
+    pub title: String,
+    pub snippet: String,
+    pub rank: f32,
+}
+
+#[async_trait]
+pub trait SearchIndex: Send + Sync {
+    async fn index(&self, kind: SearchKind, id: i32, title: &str) -> Result<(), BoxError>;
+    async fn search(&self, terms: &[String], limit: i64) -> Vec<SearchHit>;
+}
+
+pub async fn search(
+    query: &str,
+    limit: i64,
+    search_index: &impl SearchIndex,
+) -> Result<Vec<SearchHit>, ApplicationError> {
+    let terms = terms(query);
+    if terms.is_empty() {
+        return Err(ApplicationError::InvalidQuery(format!(
+            "nothing to search for in `{}`",
+            query
+        )));
+    }
+    Ok(search_index.search(&terms, limit.clamp(1, 100)).await)
+}
+
+// Only letters and digits reach the index, so user input cannot inject query operators.
+fn terms(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn terms_drop_operators() {
+        assert_eq!(terms("Good & !Omens:*"), vec!["good", "omens"]);
+        assert!(terms(" | ").is_empty());
+    }
+}
@@ -0,0 +1,34 @@
+use super::{
+    search::{SearchIndex, SearchKind},
+    BoxError, EventHandler, StoredEvent,
+};
+use crate::domain::DomainEvent;
+use async_trait::async_trait;
+
+pub struct SearchIndexer<S> {
+    search_index: S,
+}
+
+impl<S: SearchIndex> SearchIndexer<S> {
+    pub fn new(search_index: S) -> Self {
+        Self { search_index }
+    }
+}
+
+#[async_trait]
+impl<S: SearchIndex> EventHandler for SearchIndexer<S> {
+    async fn handle(&self, event: &StoredEvent) -> Result<(), BoxError> {
+        let index = &self.search_index;
+        match event.domain_event()? {
+            DomainEvent::BookCreated(e) => index.index(SearchKind::Book, e.id, &e.name).await,
+            DomainEvent::BookRenamed(e) => index.index(SearchKind::Book, e.id, &e.name).await,
+            DomainEvent::AuthorCreated(e) => {
+                index.index(SearchKind::Author, e.id, &e.full_name).await
+            }
+            DomainEvent::AuthorRenamed(e) => {
+                index.index(SearchKind::Author, e.id, &e.full_name).await
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,813 @@
+use async_std::task;
+use books::{
+    application::{
+        self, author_listing, author_listing::AuthorListItem, book_listing,
+        book_listing::BookListItem, book_projector::BookProjector, catalog::BookFilter,
+        search_indexer::SearchIndexer, ApplicationError, Position, StoredEvent,
+    },
+    domain::DomainEventPublisher,
+    infrastructure::{
+        self,
+        author::DbAuthorRepository,
+        author_listing::DbAuthorListing,
+        book::DbBookRepository,
+        book_listing::DbBookListing,
+        export::{self, ExportError, ExportFormat},
+        import::{self, ImportError, ImportReport, Importer, RowStatus},
+        projection::{ProjectionError, ProjectionRunner, ReplayProgress},
+        relay::{OutboxRelay, RelayError},
+        search::DbSearchIndex,
+        DbEventStore, DbUoW,
+    },
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::PgPool;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    process::ExitCode,
+    time::Duration,
+};
+
+#[derive(Parser)]
+#[command(name = "books", about = "Book catalog server and administration tool")]
+pub struct Cli {
+    #[arg(long, value_enum, global = true, default_value_t = Format::Table)]
+    pub format: Format,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the HTTP server (the default), migrating the database first
+    Serve,
+    /// Apply pending database migrations
+    Migrate,
+    #[command(subcommand)]
+    Author(AuthorCommand),
+    #[command(subcommand)]
+    Book(BookCommand),
+    #[command(subcommand)]
+    Events(EventsCommand),
+    /// Import authors and books from a CSV or JSON file
+    Import(ImportCommand),
+    /// Export books with their authors, streamed from the database
+    Export(ExportCommand),
+    #[command(subcommand)]
+    Projection(ProjectionCommand),
+}
+
+#[derive(Subcommand)]
+pub enum AuthorCommand {
+    Create {
+        first_name: String,
+        last_name: String,
+    },
+    Rename {
+        id: i32,
+        first_name: String,
+        last_name: String,
+    },
+    Show {
+        id: i32,
+    },
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum BookCommand {
+    Create {
+        name: String,
+        #[arg(long)]
+        pages_count: i32,
+        #[arg(long = "author", required = true)]
+        authors: Vec<i32>,
+        #[arg(long)]
+        isbn: Option<String>,
+    },
+    Show {
+        id: i32,
+    },
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum EventsCommand {
+    Tail {
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: i64,
+        #[arg(short, long)]
+        follow: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ProjectionCommand {
+    /// Replay stored_event into fresh copies of a read model's tables and swap them in
+    Rebuild {
+        #[arg(value_enum)]
+        name: ProjectionName,
+        #[arg(long, default_value_t = 500)]
+        batch_size: i64,
+    },
+}
+
+// Named as the relay registers them.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProjectionName {
+    #[value(name = "book_projector")]
+    BookProjector,
+    #[value(name = "search_indexer")]
+    SearchIndexer,
+}
+
+#[derive(clap::Args)]
+pub struct ImportCommand {
+    file: PathBuf,
+    /// Input format, guessed from the file extension by default
+    #[arg(long, value_enum)]
+    input: Option<InputFormat>,
+    /// Validate rows and resolve authors without writing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Name under which progress is saved, defaults to the file name
+    #[arg(long)]
+    job: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    Csv,
+    Json,
+}
+
+#[derive(clap::Args)]
+pub struct ExportCommand {
+    #[arg(long, value_enum, default_value_t = OutputFormat::Ndjson)]
+    to: OutputFormat,
+    /// Write to a file instead of standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[arg(long)]
+    author: Option<i32>,
+    #[arg(long)]
+    name: Option<String>,
+    #[arg(long)]
+    min_pages_count: Option<i32>,
+    #[arg(long)]
+    max_pages_count: Option<i32>,
+    #[arg(long)]
+    include_archived: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Json,
+    Csv,
+    Ndjson,
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    Application(ApplicationError),
+    Database(sqlx::Error),
+    Io(io::Error),
+    Import(ImportError),
+    Export(ExportError),
+    Relay(RelayError),
+    Projection(ProjectionError),
+    Migrate(sqlx::migrate::MigrateError),
+    RowsFailed(usize),
+}
+
+impl CliError {
+    // 2 is left to clap for usage errors.
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            CliError::Application(ApplicationError::NotFound(_)) => 3,
+            CliError::Application(ApplicationError::Domain(_))
+            | CliError::Application(ApplicationError::InvalidQuery(_))
+            | CliError::Import(ImportError::Csv(_))
+            | CliError::Import(ImportError::Json(_))
+            | CliError::RowsFailed(_) => 4,
+            CliError::Application(ApplicationError::Conflict(_)) => 5,
+            CliError::Application(_)
+            | CliError::Database(_)
+            | CliError::Io(_)
+            | CliError::Import(_)
+            | CliError::Export(_)
+            | CliError::Relay(_)
+            | CliError::Projection(_)
+            | CliError::Migrate(_) => 1,
+        })
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Application(e) => write!(f, "{}", e),
+            CliError::Database(e) => write!(f, "database error: {}", e),
+            CliError::Io(e) => write!(f, "{}", e),
+            CliError::Import(e) => write!(f, "{}", e),
+            CliError::Export(e) => write!(f, "{}", e),
+            CliError::Relay(e) => write!(f, "{}", e),
+            CliError::Projection(e) => write!(f, "{}", e),
+            CliError::Migrate(e) => write!(f, "{}", e),
+            CliError::RowsFailed(count) => write!(f, "{} row(s) failed to import", count),
+        }
+    }
+}
+
+impl From<ApplicationError> for CliError {
+    fn from(e: ApplicationError) -> Self {
+        CliError::Application(e)
+    }
+}
+
+impl From<sqlx::Error> for CliError {
+    fn from(e: sqlx::Error) -> Self {
+        CliError::Database(e)
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(e: io::Error) -> Self {
+        CliError::Io(e)
+    }
+}
+
+impl From<ImportError> for CliError {
+    fn from(e: ImportError) -> Self {
+        CliError::Import(e)
+    }
+}
+
+impl From<ExportError> for CliError {
+    fn from(e: ExportError) -> Self {
+        CliError::Export(e)
+    }
+}
+
+impl From<RelayError> for CliError {
+    fn from(e: RelayError) -> Self {
+        CliError::Relay(e)
+    }
+}
+
+impl From<ProjectionError> for CliError {
+    fn from(e: ProjectionError) -> Self {
+        CliError::Projection(e)
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for CliError {
+    fn from(e: sqlx::migrate::MigrateError) -> Self {
+        CliError::Migrate(e)
+    }
+}
+
+pub async fn migrate(pool: PgPool) -> Result<String, CliError> {
+    sqlx::migrate!().run(&pool).await?;
+    Ok(String::new())
+}
+
+pub async fn author(
+    command: AuthorCommand,
+    format: Format,
+    pool: PgPool,
+) -> Result<String, CliError> {
+    match command {
+        AuthorCommand::Create {
+            first_name,
+            last_name,
+        } => {
+            let uow = DbUoW::new(pool.clone());
+            let mut event_store = DbEventStore::new(&uow);
+            let publisher = DomainEventPublisher::new();
+            let repo = DbAuthorRepository::new(&uow, &publisher);
+            let id = application::author::create(
+                &first_name,
+                &last_name,
+                &publisher,
+                &repo,
+                &mut event_store,
+                &uow,
+            )
+            .await?;
+            project(&pool).await;
+            Ok(created(id, format))
+        }
+        AuthorCommand::Rename {
+            id,
+            first_name,
+            last_name,
+        } => {
+            let uow = DbUoW::new(pool.clone());
+            let mut event_store = DbEventStore::new(&uow);
+            let publisher = DomainEventPublisher::new();
+            let repo = DbAuthorRepository::new(&uow, &publisher);
+            application::author::update(
+                id,
+                &first_name,
+                &last_name,
+                &publisher,
+                &repo,
+                &mut event_store,
+                &uow,
+            )
+            .await?;
+            project(&pool).await;
+            Ok(String::new())
+        }
+        AuthorCommand::Show { id } => {
+            let author = author_listing::get(id, &DbAuthorListing::new(pool)).await?;
+            Ok(authors(vec![author], format))
+        }
+        AuthorCommand::List => {
+            let list = author_listing::list(&DbAuthorListing::new(pool)).await;
+            Ok(authors(list, format))
+        }
+    }
+}
+
+pub async fn book(command: BookCommand, format: Format, pool: PgPool) -> Result<String, CliError> {
+    match command {
+        BookCommand::Create {
+            name,
+            pages_count,
+            authors,
+            isbn,
+        } => {
+            let uow = DbUoW::new(pool.clone());
+            let mut event_store = DbEventStore::new(&uow);
+            let publisher = DomainEventPublisher::new();
+            let repo = DbBookRepository::new(&uow, &publisher);
+            let author_repo = DbAuthorRepository::new(&uow, &publisher);
+            let id = application::book::create(
+                &name,
+                pages_count,
+                authors,
+                isbn.as_deref(),
+                &publisher,
+                &repo,
+                &author_repo,
+                &mut event_store,
+                &uow,
+            )
+            .await?;
+            project(&pool).await;
+            Ok(created(id, format))
+        }
+        // book_listing is a projection, brought up to date here since no relay may be running.
+        BookCommand::Show { id } => {
+            OutboxRelay::projections(pool.clone(), 100).drain().await?;
+            let book = book_listing::get(id, &DbBookListing::new(pool)).await?;
+            Ok(books(vec![book], format))
+        }
+        BookCommand::List => {
+            OutboxRelay::projections(pool.clone(), 100).drain().await?;
+            let list = book_listing::list(&DbBookListing::new(pool)).await;
+            Ok(books(list, format))
+        }
+    }
+}
+
+pub async fn events(command: EventsCommand, format: Format, pool: PgPool) -> Result<(), CliError> {
+    let EventsCommand::Tail { limit, follow } = command;
+
+    let mut events = infrastructure::latest_events(&pool, limit).await?;
+    let mut position = Position::default();
+    let mut header = true;
+    loop {
+        if let Some(last) = events.last() {
+            position = last.position();
+        }
+        if !events.is_empty() {
+            print!("{}", stored_events(&events, format, header));
+            header = false;
+        }
+        if !follow {
+            return Ok(());
+        }
+
+        task::sleep(Duration::from_secs(1)).await;
+        events = infrastructure::events_after(&pool, position, limit).await?;
+    }
+}
+
+// The report is returned even when rows failed, the error only carries the exit code.
+pub async fn import(
+    command: ImportCommand,
+    format: Format,
+    pool: PgPool,
+) -> Result<String, (String, CliError)> {
+    let report = run_import(command, pool)
+        .await
+        .map_err(|e| (String::new(), e))?;
+    let out = import_report(&report, format);
+    match report.failed() {
+        0 => Ok(out),
+        failed => Err((out, CliError::RowsFailed(failed))),
+    }
+}
+
+async fn run_import(command: ImportCommand, pool: PgPool) -> Result<ImportReport, CliError> {
+    let input =
+        command
+            .input
+            .unwrap_or_else(|| match command.file.extension().and_then(|e| e.to_str()) {
+                Some("json") => InputFormat::Json,
+                _ => InputFormat::Csv,
+            });
+    let file = File::open(&command.file)?;
+    let records = match input {
+        InputFormat::Csv => import::read_csv(file)?,
+        InputFormat::Json => import::read_json(file)?,
+    };
+    let job = command
+        .job
+        .unwrap_or_else(|| command.file.to_string_lossy().into_owned());
+
+    let mut importer = Importer::new(pool.clone());
+    if command.dry_run {
+        importer = importer.dry_run();
+    }
+    let report = importer.import(&job, &records).await?;
+    if !command.dry_run {
+        project(&pool).await;
+    }
+    Ok(report)
+}
+
+// The read models (book_listing, the search index) are kept up to date by the outbox relay,
+// which only runs inside `serve`, so commands that write drain it themselves. The write is
+// committed by then: a failing handler is reported and its event stays in the outbox for the
+// next drain or for the server.
+async fn project(pool: &PgPool) {
+    if let Err(e) = OutboxRelay::projections(pool.clone(), 100).drain().await {
+        eprintln!("{}", e);
+    }
+}
+
+// Progress goes to standard error as the replay advances.
+pub async fn projection(command: ProjectionCommand, pool: PgPool) -> Result<String, CliError> {
+    let ProjectionCommand::Rebuild { name, batch_size } = command;
+
+    let runner = ProjectionRunner::new(pool, batch_size);
+    let mut progress = |p: ReplayProgress| eprintln!("{}/{}", p.processed, p.total);
+    match name {
+        ProjectionName::BookProjector => {
+            runner
+                .rebuild(
+                    "book_projector",
+                    DbBookListing::TABLES,
+                    |pool| BookProjector::new(DbBookListing::new(pool)),
+                    &mut progress,
+                )
+                .await?
+        }
+        ProjectionName::SearchIndexer => {
+            runner
+                .rebuild(
+                    "search_indexer",
+                    DbSearchIndex::TABLES,
+                    |pool| SearchIndexer::new(DbSearchIndex::new(pool)),
+                    &mut progress,
+                )
+                .await?
+        }
+    };
+    Ok(String::new())
+}
+
+// The global `--format` only applies to reports, the export format is chosen with `--to`.
+pub async fn export(command: ExportCommand, pool: PgPool) -> Result<(), CliError> {
+    let filter = BookFilter {
+        author_id: command.author,
+        name: command.name,
+        min_pages_count: command.min_pages_count,
+        max_pages_count: command.max_pages_count,
+        include_archived: command.include_archived,
+    };
+    let format = match command.to {
+        OutputFormat::Json => ExportFormat::Json,
+        OutputFormat::Csv => ExportFormat::Csv,
+        OutputFormat::Ndjson => ExportFormat::Ndjson,
+    };
+    let writer: Box<dyn Write> = match command.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+
+    export::export_books(&pool, &filter, format, BufWriter::new(writer)).await?;
+    Ok(())
+}
+
+fn import_report(report: &ImportReport, format: Format) -> String {
+    match format {
+        Format::Json => format!("{}\n", serde_json::to_string_pretty(report).unwrap()),
+        Format::Table => {
+            let rows = report
+                .rows
+                .iter()
+                .map(|r| {
+                    let (status, detail) = match &r.status {
+                        RowStatus::Created { id } => ("created", id.to_string()),
+                        RowStatus::Valid => ("valid", String::new()),
+                        RowStatus::Skipped { id } => ("skipped", id.to_string()),
+                        RowStatus::Failed { errors } => ("failed", errors.join("; ")),
+                    };
+                    vec![
+                        r.row.to_string(),
+                        String::from(r.kind.name()),
+                        r.key.clone().unwrap_or_default(),
+                        String::from(status),
+                        detail,
+                    ]
+                })
+                .collect();
+            table(&["ROW", "KIND", "KEY", "STATUS", "DETAIL"], rows)
+        }
+    }
+}
+
+fn created(id: i32, format: Format) -> String {
+    match format {
+        Format::Table => format!("{}\n", id),
+        Format::Json => format!("{}\n", json!({ "id": id })),
+    }
+}
+
+fn authors(authors: Vec<AuthorListItem>, format: Format) -> String {
+    render(
+        &authors,
+        format,
+        &["ID", "FIRST NAME", "LAST NAME", "ARCHIVED"],
+        |a| {
+            vec![
+                a.id.to_string(),
+                a.first_name.clone(),
+                a.last_name.clone(),
+                a.archived.to_string(),
+            ]
+        },
+    )
+}
+
+fn books(books: Vec<BookListItem>, format: Format) -> String {
+    render(
+        &books,
+        format,
+        &["ID", "NAME", "PAGES", "AUTHORS", "ISBN"],
+        |b| {
+            vec![
+                b.id.to_string(),
+                b.name.clone(),
+                b.pages_count.to_string(),
+                b.authors.clone(),
+                b.isbn.clone().unwrap_or_default(),
+            ]
+        },
+    )
+}
+
+// JSON output is one object per line so that `events tail --follow` can be piped.
+fn stored_events(events: &[StoredEvent], format: Format, header: bool) -> String {
+    match format {
+        Format::Json => events
+            .iter()
+            .map(|e| {
+                let payload: serde_json::Value =
+                    serde_json::from_str(e.playload()).unwrap_or_default();
+                format!(
+                    "{}\n",
+                    json!({
+                        "id": e.id(),
+                        "aggregate_type": e.aggregate_type(),
+                        "aggregate_id": e.aggregate_id(),
+                        "sequence": e.sequence(),
+                        "name": e.name(),
+                        "payload": payload,
+                    })
+                )
+            })
+            .collect(),
+        Format::Table => {
+            let rows = events
+                .iter()
+                .map(|e| {
+                    vec![
+                        e.id().to_string(),
+                        format!("{} {}", e.aggregate_type(), e.aggregate_id()),
+                        e.sequence().to_string(),
+                        e.name().to_string(),
+                    ]
+                })
+                .collect();
+            let headers: &[&str] = if header {
+                &["ID", "STREAM", "SEQUENCE", "NAME"]
+            } else {
+                &[]
+            };
+            table(headers, rows)
+        }
+    }
+}
+
+fn render<T: Serialize>(
+    items: &[T],
+    format: Format,
+    headers: &[&str],
+    row: impl Fn(&T) -> Vec<String>,
+) -> String {
+    match format {
+        Format::Json => format!("{}\n", serde_json::to_string_pretty(items).unwrap()),
+        Format::Table => table(headers, items.iter().map(row).collect()),
+    }
+}
+
+fn table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let columns = headers.len().max(rows.first().map_or(0, |r| r.len()));
+    let mut widths = vec![0; columns];
+    for (i, header) in headers.iter().enumerate() {
+        widths[i] = header.len();
+    }
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let cells: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        format!("{}\n", cells.join("  ").trim_end())
+    };
+
+    let mut out = String::new();
+    if !headers.is_empty() {
+        out.push_str(&line(headers.to_vec()));
+    }
+    for row in &rows {
+        out.push_str(&line(row.iter().map(String::as_str).collect()));
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[sqlx::test]
+    async fn author_commands(pool: PgPool) {
+        let id = author(
+            AuthorCommand::Create {
+                first_name: String::from("f1"),
+                last_name: String::from("l1"),
+            },
+            Format::Table,
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(id, "1\n");
+
+        author(
+            AuthorCommand::Rename {
+                id: 1,
+                first_name: String::from("f2"),
+                last_name: String::from("l2"),
+            },
+            Format::Table,
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+
+        let out = author(AuthorCommand::List, Format::Table, pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            out,
+            "ID  FIRST NAME  LAST NAME  ARCHIVED\n1   f2          l2         false\n"
+        );
+
+        let error = author(AuthorCommand::Show { id: 42 }, Format::Json, pool.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(error.exit_code(), ExitCode::from(3));
+
+        let error = book(
+            BookCommand::Create {
+                name: String::new(),
+                pages_count: 10,
+                authors: vec![1],
+                isbn: None,
+            },
+            Format::Json,
+            pool,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.exit_code(), ExitCode::from(4));
+    }
+
+    #[sqlx::test]
+    async fn book_commands(pool: PgPool) {
+        author(
+            AuthorCommand::Create {
+                first_name: String::from("f1"),
+                last_name: String::from("l1"),
+            },
+            Format::Table,
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        let id = book(
+            BookCommand::Create {
+                name: String::from("book1"),
+                pages_count: 10,
+                authors: vec![1],
+                isbn: Some(String::from("0-306-40615-2")),
+            },
+            Format::Table,
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(id, "1\n");
+
+        let out = book(BookCommand::Show { id: 1 }, Format::Table, pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            out,
+            "ID  NAME   PAGES  AUTHORS  ISBN\n1   book1  10     f1 l1    9780306406157\n"
+        );
+
+        let out = book(BookCommand::List, Format::Table, pool).await.unwrap();
+        assert_eq!(
+            out,
+            "ID  NAME   PAGES  AUTHORS  ISBN\n1   book1  10     f1 l1    9780306406157\n"
+        );
+    }
+
+    #[sqlx::test]
+    async fn writes_are_searchable(pool: PgPool) {
+        author(
+            AuthorCommand::Create {
+                first_name: String::from("Terry"),
+                last_name: String::from("Pratchett"),
+            },
+            Format::Table,
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        let index = DbSearchIndex::new(pool.clone());
+        assert_eq!(
+            application::search::search("prat", 10, &index)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        sqlx::query("truncate search_document")
+            .execute(&pool)
+            .await
+            .unwrap();
+        projection(
+            ProjectionCommand::Rebuild {
+                name: ProjectionName::SearchIndexer,
+                batch_size: 10,
+            },
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            application::search::search("prat", 10, &index)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
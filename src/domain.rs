@@ -1,59 +1,578 @@
 pub mod author;
 pub mod book;
+pub mod isbn;
 
+use async_trait::async_trait;
 use author::*;
 use book::*;
-use serde::Serialize;
-use std::sync::RwLock;
+use futures::future;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DomainEvent {
     BookCreated(BookCreated),
     BookRenamed(BookRenamed),
+    BookPagesCountChanged(BookPagesCountChanged),
+    BookIsbnChanged(BookIsbnChanged),
+    BookAuthorAdded(BookAuthorAdded),
+    BookAuthorRemoved(BookAuthorRemoved),
+    BookArchived(BookArchived),
+    BookRestored(BookRestored),
     AuthorCreated(AuthorCreated),
     AuthorRenamed(AuthorRenamed),
+    AuthorArchived(AuthorArchived),
+    AuthorRestored(AuthorRestored),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Book,
+    Author,
+}
+
+impl Aggregate {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aggregate::Book => "book",
+            Aggregate::Author => "author",
+        }
+    }
 }
 
 impl DomainEvent {
+    pub fn stream(&self) -> (Aggregate, i32) {
+        match self {
+            DomainEvent::BookCreated(e) => (Aggregate::Book, e.id),
+            DomainEvent::BookRenamed(e) => (Aggregate::Book, e.id),
+            DomainEvent::BookPagesCountChanged(e) => (Aggregate::Book, e.id),
+            DomainEvent::BookIsbnChanged(e) => (Aggregate::Book, e.id),
+            DomainEvent::BookAuthorAdded(e) => (Aggregate::Book, e.id),
+            DomainEvent::BookAuthorRemoved(e) => (Aggregate::Book, e.id),
+            DomainEvent::BookArchived(e) => (Aggregate::Book, e.id),
+            DomainEvent::BookRestored(e) => (Aggregate::Book, e.id),
+            DomainEvent::AuthorCreated(e) => (Aggregate::Author, e.id),
+            DomainEvent::AuthorRenamed(e) => (Aggregate::Author, e.id),
+            DomainEvent::AuthorArchived(e) => (Aggregate::Author, e.id),
+            DomainEvent::AuthorRestored(e) => (Aggregate::Author, e.id),
+        }
+    }
+
     pub fn domain_event_name(&self) -> &'static str {
         match self {
             DomainEvent::BookCreated(_) => "book_created",
             DomainEvent::BookRenamed(_) => "book_renamed",
+            DomainEvent::BookPagesCountChanged(_) => "book_pages_count_changed",
+            DomainEvent::BookIsbnChanged(_) => "book_isbn_changed",
+            DomainEvent::BookAuthorAdded(_) => "book_author_added",
+            DomainEvent::BookAuthorRemoved(_) => "book_author_removed",
+            DomainEvent::BookArchived(_) => "book_archived",
+            DomainEvent::BookRestored(_) => "book_restored",
             DomainEvent::AuthorCreated(_) => "author_created",
             DomainEvent::AuthorRenamed(_) => "author_renamed",
+            DomainEvent::AuthorArchived(_) => "author_archived",
+            DomainEvent::AuthorRestored(_) => "author_restored",
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomainError {
+    EmptyBookName,
+    NonPositivePagesCount(i32),
+    NoBookAuthors,
+    DuplicateBookAuthors(Vec<i32>),
+    UnknownAuthors(Vec<i32>),
+    InvalidIsbn(String),
+    DuplicateIsbn(String),
+    EmptyFirstName,
+    EmptyLastName,
+    BookArchived(i32),
+    AuthorArchived(i32),
+    AuthorHasActiveBooks(Vec<i32>),
+    InvalidReassignment(i32),
+}
+
+impl fmt::Display for DomainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DomainError::EmptyBookName => write!(f, "book name must not be empty"),
+            DomainError::NonPositivePagesCount(pages_count) => {
+                write!(f, "pages count must be positive, got {}", pages_count)
+            }
+            DomainError::NoBookAuthors => write!(f, "book must have at least one author"),
+            DomainError::DuplicateBookAuthors(ids) => {
+                write!(f, "book authors are listed more than once {:?}", ids)
+            }
+            DomainError::UnknownAuthors(ids) => write!(f, "authors {:?} do not exist", ids),
+            DomainError::InvalidIsbn(isbn) => write!(f, "`{}` is not a valid ISBN", isbn),
+            DomainError::DuplicateIsbn(isbn) => {
+                write!(f, "ISBN {} is already used by another book", isbn)
+            }
+            DomainError::EmptyFirstName => write!(f, "author first name must not be empty"),
+            DomainError::EmptyLastName => write!(f, "author last name must not be empty"),
+            DomainError::BookArchived(id) => write!(f, "book {} is archived", id),
+            DomainError::AuthorArchived(id) => write!(f, "author {} is archived", id),
+            DomainError::AuthorHasActiveBooks(books) => {
+                write!(f, "author still has active books {:?}", books)
+            }
+            DomainError::InvalidReassignment(id) => {
+                write!(f, "books cannot be reassigned to author {}", id)
+            }
+        }
+    }
+}
+
+impl Error for DomainError {}
+
+pub trait Event: Sized {
+    fn from_domain_event(e: &DomainEvent) -> Option<&Self>;
+}
+
+macro_rules! impl_event {
+    ($($variant:ident),* $(,)?) => {
+        $(
+            impl Event for $variant {
+                fn from_domain_event(e: &DomainEvent) -> Option<&Self> {
+                    match e {
+                        DomainEvent::$variant(e) => Some(e),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_event!(
+    BookCreated,
+    BookRenamed,
+    BookPagesCountChanged,
+    BookIsbnChanged,
+    BookAuthorAdded,
+    BookAuthorRemoved,
+    BookArchived,
+    BookRestored,
+    AuthorCreated,
+    AuthorRenamed,
+    AuthorArchived,
+    AuthorRestored,
+);
+
+type Handler<'a> = Box<dyn FnMut(&DomainEvent) + Send + Sync + 'a>;
+
+// Async handlers run before the unit of work commits, so their failure can still roll it
+// back, and a commit that fails afterwards cannot undo what they did. They must only write
+// through the same unit of work or have no effects at all; anything external belongs in an
+// after-commit subscription or an outbox relay handler.
+#[async_trait]
+pub trait AsyncEventHandler: Send + Sync {
+    async fn handle(&self, e: &DomainEvent) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+#[derive(Debug)]
+pub struct HandlerFailure {
+    pub event: &'static str,
+    pub source: Box<dyn Error + Send + Sync>,
+}
+
+impl fmt::Display for HandlerFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "handler of {} failed: {}", self.event, self.source)
+    }
+}
+
+impl Error for HandlerFailure {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DispatchMode {
+    // Handlers run one after another and dispatch stops at the first failure.
+    #[default]
+    Sequential,
+    // Handlers of an event run together and every failure is reported.
+    Concurrent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriptionHandle(usize);
+
+struct Subscription<H> {
+    handle: SubscriptionHandle,
+    priority: i32,
+    handler: H,
+}
+
+fn insert_by_priority<H>(entries: &mut Vec<Subscription<H>>, subscription: Subscription<H>) {
+    let index = entries
+        .iter()
+        .position(|s| s.priority < subscription.priority)
+        .unwrap_or(entries.len());
+    entries.insert(index, subscription);
+}
+
+#[derive(Default)]
+struct Subscriptions<'a> {
+    next_handle: usize,
+    entries: Vec<Subscription<Handler<'a>>>,
+    async_entries: Vec<Subscription<Arc<dyn AsyncEventHandler + 'a>>>,
+    committed_entries: Vec<Subscription<Handler<'a>>>,
+}
+
+impl<'a> Subscriptions<'a> {
+    fn next_handle(&mut self) -> SubscriptionHandle {
+        let handle = SubscriptionHandle(self.next_handle);
+        self.next_handle += 1;
+        handle
+    }
+}
+
+// Async handlers cannot run inside `publish`, which aggregates call synchronously, so the
+// events are kept until `dispatch` hands them to the async handlers. Likewise for
+// after-commit subscriptions, which get them from `committed` once the commit succeeded.
+#[derive(Default)]
 pub struct DomainEventPublisher<'a> {
-    handlers: RwLock<Vec<Box<dyn FnMut(&DomainEvent) + Send + Sync + 'a>>>,
+    subscriptions: RwLock<Subscriptions<'a>>,
+    pending: Mutex<Vec<DomainEvent>>,
+    uncommitted: Mutex<Vec<DomainEvent>>,
+    mode: DispatchMode,
 }
 
 impl<'a> DomainEventPublisher<'a> {
+    pub const DEFAULT_PRIORITY: i32 = 0;
+
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_mode(mode: DispatchMode) -> Self {
         Self {
-            handlers: RwLock::new(Vec::new()),
+            mode,
+            ..Self::default()
         }
     }
 
     pub fn publish(&self, e: &DomainEvent) {
-        for handler in self.handlers.write().unwrap().iter_mut() {
-            handler(e);
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        for subscription in subscriptions.entries.iter_mut() {
+            (subscription.handler)(e);
+        }
+        if !subscriptions.async_entries.is_empty() {
+            self.pending.lock().unwrap().push(e.clone());
+        }
+        if !subscriptions.committed_entries.is_empty() {
+            self.uncommitted.lock().unwrap().push(e.clone());
+        }
+    }
+
+    pub fn committed(&self) {
+        let events = std::mem::take(&mut *self.uncommitted.lock().unwrap());
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        for e in &events {
+            for subscription in subscriptions.committed_entries.iter_mut() {
+                (subscription.handler)(e);
+            }
+        }
+    }
+
+    // Forgets the events of a unit of work that was rolled back or failed to commit.
+    pub fn discard(&self) {
+        self.pending.lock().unwrap().clear();
+        self.uncommitted.lock().unwrap().clear();
+    }
+
+    pub async fn dispatch(&self) -> Result<(), Vec<HandlerFailure>> {
+        let events = std::mem::take(&mut *self.pending.lock().unwrap());
+        let handlers: Vec<Arc<dyn AsyncEventHandler + 'a>> = self
+            .subscriptions
+            .read()
+            .unwrap()
+            .async_entries
+            .iter()
+            .map(|s| s.handler.clone())
+            .collect();
+
+        for e in &events {
+            let failures: Vec<HandlerFailure> = match self.mode {
+                DispatchMode::Sequential => {
+                    let mut failures = Vec::new();
+                    for handler in &handlers {
+                        if let Err(source) = handler.handle(e).await {
+                            failures.push(HandlerFailure {
+                                event: e.domain_event_name(),
+                                source,
+                            });
+                            break;
+                        }
+                    }
+                    failures
+                }
+                DispatchMode::Concurrent => future::join_all(handlers.iter().map(|h| h.handle(e)))
+                    .await
+                    .into_iter()
+                    .filter_map(Result::err)
+                    .map(|source| HandlerFailure {
+                        event: e.domain_event_name(),
+                        source,
+                    })
+                    .collect(),
+            };
+
+            if !failures.is_empty() {
+                return Err(failures);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn subscribe(
+        &self,
+        handle: impl FnMut(&DomainEvent) + Send + Sync + 'a,
+    ) -> SubscriptionHandle {
+        self.subscribe_with_priority(Self::DEFAULT_PRIORITY, handle)
+    }
+
+    // Higher priorities run first; handlers of equal priority run in subscription order.
+    pub fn subscribe_with_priority(
+        &self,
+        priority: i32,
+        handle: impl FnMut(&DomainEvent) + Send + Sync + 'a,
+    ) -> SubscriptionHandle {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        let handle_id = subscriptions.next_handle();
+        insert_by_priority(
+            &mut subscriptions.entries,
+            Subscription {
+                handle: handle_id,
+                priority,
+                handler: Box::new(handle),
+            },
+        );
+        handle_id
+    }
+
+    pub fn subscribe_to<E: Event>(
+        &self,
+        handle: impl FnMut(&E) + Send + Sync + 'a,
+    ) -> SubscriptionHandle {
+        self.subscribe_to_with_priority(Self::DEFAULT_PRIORITY, handle)
+    }
+
+    pub fn subscribe_to_with_priority<E: Event>(
+        &self,
+        priority: i32,
+        mut handle: impl FnMut(&E) + Send + Sync + 'a,
+    ) -> SubscriptionHandle {
+        self.subscribe_with_priority(priority, move |e| {
+            if let Some(e) = E::from_domain_event(e) {
+                handle(e);
+            }
+        })
+    }
+
+    pub fn subscribe_async(&self, handler: impl AsyncEventHandler + 'a) -> SubscriptionHandle {
+        self.subscribe_async_with_priority(Self::DEFAULT_PRIORITY, handler)
+    }
+
+    pub fn subscribe_async_with_priority(
+        &self,
+        priority: i32,
+        handler: impl AsyncEventHandler + 'a,
+    ) -> SubscriptionHandle {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        let handle_id = subscriptions.next_handle();
+        insert_by_priority(
+            &mut subscriptions.async_entries,
+            Subscription {
+                handle: handle_id,
+                priority,
+                handler: Arc::new(handler),
+            },
+        );
+        handle_id
+    }
+
+    pub fn subscribe_after_commit(
+        &self,
+        handle: impl FnMut(&DomainEvent) + Send + Sync + 'a,
+    ) -> SubscriptionHandle {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        let handle_id = subscriptions.next_handle();
+        insert_by_priority(
+            &mut subscriptions.committed_entries,
+            Subscription {
+                handle: handle_id,
+                priority: Self::DEFAULT_PRIORITY,
+                handler: Box::new(handle),
+            },
+        );
+        handle_id
+    }
+
+    pub fn unsubscribe(&self, handle: SubscriptionHandle) -> bool {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        let count = subscriptions.entries.len()
+            + subscriptions.async_entries.len()
+            + subscriptions.committed_entries.len();
+        subscriptions.entries.retain(|s| s.handle != handle);
+        subscriptions.async_entries.retain(|s| s.handle != handle);
+        subscriptions
+            .committed_entries
+            .retain(|s| s.handle != handle);
+        subscriptions.entries.len()
+            + subscriptions.async_entries.len()
+            + subscriptions.committed_entries.len()
+            != count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn renamed(id: i32) -> DomainEvent {
+        DomainEvent::BookRenamed(BookRenamed {
+            id,
+            previous_name: String::from("book"),
+            name: String::from("book-renamed"),
+        })
+    }
+
+    #[test]
+    fn subscribe_to_receives_only_its_event() {
+        let renamed_ids = Mutex::new(Vec::new());
+        let archived_ids = Mutex::new(Vec::new());
+        {
+            let publisher = DomainEventPublisher::new();
+            publisher.subscribe_to(|e: &BookRenamed| renamed_ids.lock().unwrap().push(e.id));
+            publisher.subscribe_to::<BookArchived>(|e| archived_ids.lock().unwrap().push(e.id));
+
+            publisher.publish(&renamed(1));
+            publisher.publish(&DomainEvent::BookArchived(BookArchived { id: 2 }));
         }
+
+        assert_eq!(*renamed_ids.lock().unwrap(), vec![1]);
+        assert_eq!(*archived_ids.lock().unwrap(), vec![2]);
     }
 
-    pub fn subscribe(&self, handle: impl FnMut(&DomainEvent) + Send + Sync + 'a) {
-        self.handlers.write().unwrap().push(Box::new(handle));
+    #[test]
+    fn handlers_run_by_priority_then_subscription_order() {
+        let calls = Mutex::new(Vec::new());
+        {
+            let publisher = DomainEventPublisher::new();
+            publisher.subscribe(|_| calls.lock().unwrap().push("default-1"));
+            publisher.subscribe_with_priority(-10, |_| calls.lock().unwrap().push("low"));
+            publisher.subscribe_to_with_priority(10, |_: &BookRenamed| {
+                calls.lock().unwrap().push("high")
+            });
+            publisher.subscribe(|_| calls.lock().unwrap().push("default-2"));
+
+            publisher.publish(&renamed(1));
+        }
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["high", "default-1", "default-2", "low"]
+        );
     }
 
-    // pub fn subscribe_on_product_updated(
-    //     &self,
-    //     mut handle: impl FnMut(&BookRenamed) + Send + Sync + 'a,
-    // ) {
-    //     self.subscribe(move |e| {
-    //         if let DomainEvent::BookRenamed(e) = e {
-    //             handle(e);
-    //         }
-    //     })
-    // }
+    #[test]
+    fn unsubscribe() {
+        let calls = Mutex::new(0);
+        {
+            let publisher = DomainEventPublisher::new();
+            let handle = publisher.subscribe_to(|_: &BookRenamed| *calls.lock().unwrap() += 1);
+
+            publisher.publish(&renamed(1));
+            assert!(publisher.unsubscribe(handle));
+            assert!(!publisher.unsubscribe(handle));
+            publisher.publish(&renamed(1));
+        }
+
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn after_commit_subscriptions_wait_for_committed() {
+        let ids = Mutex::new(Vec::new());
+        {
+            let publisher = DomainEventPublisher::new();
+            publisher.subscribe_after_commit(|e| ids.lock().unwrap().push(e.stream().1));
+
+            publisher.publish(&renamed(1));
+            publisher.discard();
+            publisher.publish(&renamed(2));
+            assert!(ids.lock().unwrap().is_empty());
+            publisher.committed();
+            publisher.committed();
+        }
+
+        assert_eq!(*ids.lock().unwrap(), vec![2]);
+    }
+
+    struct Recorder {
+        name: &'static str,
+        fail: bool,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    #[async_trait]
+    impl AsyncEventHandler for Recorder {
+        async fn handle(&self, _: &DomainEvent) -> Result<(), Box<dyn Error + Send + Sync>> {
+            self.calls.lock().unwrap().push(self.name);
+            if self.fail {
+                Err(format!("{} failed", self.name).into())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn recorders(publisher: &DomainEventPublisher) -> Arc<Mutex<Vec<&'static str>>> {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        for (name, fail) in [("first", false), ("second", true), ("third", true)] {
+            publisher.subscribe_async(Recorder {
+                name,
+                fail,
+                calls: calls.clone(),
+            });
+        }
+        calls
+    }
+
+    #[async_std::test]
+    async fn sequential_dispatch_stops_at_first_failure() {
+        let publisher = DomainEventPublisher::new();
+        let calls = recorders(&publisher);
+
+        publisher.publish(&renamed(1));
+        publisher.publish(&renamed(2));
+        let failures = publisher.dispatch().await.unwrap_err();
+
+        assert_eq!(*calls.lock().unwrap(), vec!["first", "second"]);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].event, "book_renamed");
+        assert!(publisher.dispatch().await.is_ok());
+    }
+
+    #[async_std::test]
+    async fn concurrent_dispatch_reports_every_failure() {
+        let publisher = DomainEventPublisher::with_mode(DispatchMode::Concurrent);
+        let calls = recorders(&publisher);
+
+        publisher.publish(&renamed(1));
+        let failures = publisher.dispatch().await.unwrap_err();
+
+        assert_eq!(calls.lock().unwrap().len(), 3);
+        let failures: Vec<String> = failures.iter().map(|f| f.source.to_string()).collect();
+        assert_eq!(failures, vec!["second failed", "third failed"]);
+    }
 }
@@ -1,12 +1,16 @@
-use super::{DomainEvent, DomainEventPublisher};
+use super::{DomainError, DomainEvent, DomainEventPublisher};
 use async_trait::async_trait;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
 
 pub struct Author<'a, 'b> {
     id: i32,
     first_name: String,
     last_name: String,
     full_name: String,
+    archived: bool,
+    version: i32,
+    changes: i32,
     publisher: &'a DomainEventPublisher<'b>,
 }
 
@@ -16,6 +20,8 @@ impl<'a, 'b> Author<'a, 'b> {
         first_name: &str,
         last_name: &str,
         full_name: &str,
+        archived: bool,
+        version: i32,
         publisher: &'a DomainEventPublisher<'b>,
     ) -> Self {
         Self {
@@ -23,6 +29,9 @@ impl<'a, 'b> Author<'a, 'b> {
             first_name: String::from(first_name),
             last_name: String::from(last_name),
             full_name: String::from(full_name),
+            archived,
+            version,
+            changes: 0,
             publisher,
         }
     }
@@ -32,26 +41,47 @@ impl<'a, 'b> Author<'a, 'b> {
         first_name: &str,
         last_name: &str,
         publisher: &'a DomainEventPublisher<'b>,
-    ) -> Self {
-        assert!(!first_name.is_empty());
-        assert!(!last_name.is_empty());
+    ) -> Result<Self, Vec<DomainError>> {
+        Author::validate(first_name, last_name)?;
 
-        let author = Self {
-            id,
-            first_name: String::from(first_name),
-            last_name: String::from(last_name),
-            full_name: Author::calculate_full_name(first_name, last_name),
-            publisher,
-        };
+        let full_name = Author::calculate_full_name(first_name, last_name);
+        let mut author =
+            Author::materialize(id, first_name, last_name, &full_name, false, 0, publisher);
 
-        publisher.publish(&DomainEvent::AuthorCreated(AuthorCreated {
+        author.publish(DomainEvent::AuthorCreated(AuthorCreated {
             id,
             first_name: String::from(first_name),
             last_name: String::from(last_name),
-            full_name: String::from(&author.full_name),
+            full_name,
         }));
 
-        author
+        Ok(author)
+    }
+
+    pub fn from_history(
+        history: impl IntoIterator<Item = DomainEvent>,
+        publisher: &'a DomainEventPublisher<'b>,
+    ) -> Option<Self> {
+        let mut history = history.into_iter();
+        let mut author = match history.next()? {
+            DomainEvent::AuthorCreated(e) => Author::materialize(
+                e.id,
+                &e.first_name,
+                &e.last_name,
+                &e.full_name,
+                false,
+                1,
+                publisher,
+            ),
+            _ => return None,
+        };
+
+        for e in history {
+            author.apply(e);
+            author.version += 1;
+        }
+
+        Some(author)
     }
 
     pub fn id(&self) -> i32 {
@@ -70,22 +100,115 @@ impl<'a, 'b> Author<'a, 'b> {
         &self.full_name
     }
 
-    pub fn update(&mut self, first_name: &str, last_name: &str) {
-        assert!(!first_name.is_empty());
-        assert!(!last_name.is_empty());
+    pub fn archived(&self) -> bool {
+        self.archived
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn next_version(&self) -> i32 {
+        self.version + self.changes
+    }
+
+    pub fn update(&mut self, first_name: &str, last_name: &str) -> Result<(), Vec<DomainError>> {
+        if self.archived {
+            return Err(vec![DomainError::AuthorArchived(self.id)]);
+        }
+        Author::validate(first_name, last_name)?;
 
         if first_name != self.first_name || last_name != self.last_name {
-            self.first_name = String::from(first_name);
-            self.last_name = String::from(last_name);
-            self.full_name = Author::calculate_full_name(first_name, last_name);
-
-            self.publisher
-                .publish(&DomainEvent::AuthorCreated(AuthorCreated {
-                    id: self.id,
-                    first_name: String::from(first_name),
-                    last_name: String::from(last_name),
-                    full_name: String::from(&self.full_name),
-                }));
+            let full_name = Author::calculate_full_name(first_name, last_name);
+            let previous_first_name =
+                std::mem::replace(&mut self.first_name, String::from(first_name));
+            let previous_last_name =
+                std::mem::replace(&mut self.last_name, String::from(last_name));
+            let previous_full_name = std::mem::replace(&mut self.full_name, full_name);
+
+            self.publish(DomainEvent::AuthorRenamed(AuthorRenamed {
+                id: self.id,
+                previous_first_name,
+                previous_last_name,
+                previous_full_name,
+                first_name: String::from(first_name),
+                last_name: String::from(last_name),
+                full_name: String::from(&self.full_name),
+            }));
+        }
+
+        Ok(())
+    }
+
+    pub fn archive(
+        &mut self,
+        active_books: &[i32],
+        reassign_to: Option<&Author>,
+    ) -> Result<(), Vec<DomainError>> {
+        if self.archived {
+            return Ok(());
+        }
+
+        let reassigned_to = match reassign_to {
+            Some(author) if author.id == self.id || author.archived => {
+                return Err(vec![DomainError::InvalidReassignment(author.id)]);
+            }
+            Some(author) => Some(author.id),
+            None if !active_books.is_empty() => {
+                return Err(vec![DomainError::AuthorHasActiveBooks(
+                    active_books.to_vec(),
+                )]);
+            }
+            None => None,
+        };
+
+        self.archived = true;
+        self.publish(DomainEvent::AuthorArchived(AuthorArchived {
+            id: self.id,
+            reassigned_to,
+        }));
+
+        Ok(())
+    }
+
+    pub fn restore(&mut self) {
+        if self.archived {
+            self.archived = false;
+            self.publish(DomainEvent::AuthorRestored(AuthorRestored { id: self.id }));
+        }
+    }
+
+    fn publish(&mut self, e: DomainEvent) {
+        self.changes += 1;
+        self.publisher.publish(&e);
+    }
+
+    fn apply(&mut self, e: DomainEvent) {
+        match e {
+            DomainEvent::AuthorRenamed(e) => {
+                self.first_name = e.first_name;
+                self.last_name = e.last_name;
+                self.full_name = e.full_name;
+            }
+            DomainEvent::AuthorArchived(_) => self.archived = true,
+            DomainEvent::AuthorRestored(_) => self.archived = false,
+            _ => {}
+        }
+    }
+
+    fn validate(first_name: &str, last_name: &str) -> Result<(), Vec<DomainError>> {
+        let mut errors = Vec::new();
+        if first_name.is_empty() {
+            errors.push(DomainError::EmptyFirstName);
+        }
+        if last_name.is_empty() {
+            errors.push(DomainError::EmptyLastName);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
     }
 
@@ -98,22 +221,104 @@ impl<'a, 'b> Author<'a, 'b> {
 pub trait AuthorRepository<'a, 'b> {
     fn create(&self, author: &Author);
     fn update(&self, author: &Author);
+    fn archive(&self, author: &Author);
+    fn restore(&self, author: &Author);
     async fn next_identity(&self) -> i32;
-    async fn by_id(&self, id: i32) -> Option<Author<'a, 'b>>;
+    async fn by_id(&self, id: i32) -> Result<Option<Author<'a, 'b>>, Box<dyn Error + Send + Sync>>;
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthorCreated {
-    id: i32,
-    first_name: String,
-    last_name: String,
-    full_name: String,
+    pub id: i32,
+    pub first_name: String,
+    pub last_name: String,
+    pub full_name: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthorRenamed {
-    id: i32,
-    first_name: String,
-    last_name: String,
-    full_name: String,
+    pub id: i32,
+    pub previous_first_name: String,
+    pub previous_last_name: String,
+    pub previous_full_name: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub full_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorArchived {
+    pub id: i32,
+    pub reassigned_to: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorRestored {
+    pub id: i32,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn new_collects_every_violation() {
+        let publisher = DomainEventPublisher::new();
+
+        let errors = Author::new(1, "", "", &publisher).err().unwrap();
+
+        assert_eq!(
+            errors,
+            vec![DomainError::EmptyFirstName, DomainError::EmptyLastName]
+        );
+    }
+
+    #[test]
+    fn update_publishes_author_renamed() {
+        let mut events = Vec::new();
+        {
+            let publisher = DomainEventPublisher::new();
+            publisher.subscribe(|e| events.push(serde_json::to_value(e).unwrap()));
+
+            let mut author = Author::materialize(1, "f1", "l1", "f1 l1", false, 1, &publisher);
+            author.update("f1", "l1").unwrap();
+            author.update("f2", "l1").unwrap();
+        }
+
+        assert_eq!(
+            events,
+            vec![serde_json::json!({
+                "AuthorRenamed": {
+                    "id": 1,
+                    "previous_first_name": "f1",
+                    "previous_last_name": "l1",
+                    "previous_full_name": "f1 l1",
+                    "first_name": "f2",
+                    "last_name": "l1",
+                    "full_name": "f2 l1",
+                }
+            })]
+        );
+    }
+
+    #[test]
+    fn archive_with_active_books_requires_reassignment() {
+        let publisher = DomainEventPublisher::new();
+        let mut author = Author::materialize(1, "f1", "l1", "f1 l1", false, 1, &publisher);
+        let other = Author::materialize(2, "f2", "l2", "f2 l2", false, 1, &publisher);
+
+        let errors = author.archive(&[10, 11], None).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![DomainError::AuthorHasActiveBooks(vec![10, 11])]
+        );
+        assert!(!author.archived());
+
+        let same = Author::materialize(1, "f1", "l1", "f1 l1", false, 1, &publisher);
+        let errors = author.archive(&[10, 11], Some(&same)).unwrap_err();
+        assert_eq!(errors, vec![DomainError::InvalidReassignment(1)]);
+
+        author.archive(&[10, 11], Some(&other)).unwrap();
+        assert!(author.archived());
+    }
 }
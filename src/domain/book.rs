@@ -1,21 +1,30 @@
-use super::{DomainEvent, DomainEventPublisher};
+use super::{isbn::Isbn, DomainError, DomainEvent, DomainEventPublisher};
 use async_trait::async_trait;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
 
 pub struct Book<'a, 'b> {
     id: i32,
     name: String,
     pages_count: i32,
     authors: Vec<i32>,
+    isbn: Option<Isbn>,
+    archived: bool,
+    version: i32,
+    changes: i32,
     publisher: &'a DomainEventPublisher<'b>,
 }
 
 impl<'a, 'b> Book<'a, 'b> {
+    #[allow(clippy::too_many_arguments)]
     pub fn materialize(
         id: i32,
         name: &str,
         pages_count: i32,
         authors: Vec<i32>,
+        isbn: Option<Isbn>,
+        archived: bool,
+        version: i32,
         publisher: &'a DomainEventPublisher<'b>,
     ) -> Self {
         Self {
@@ -23,6 +32,10 @@ impl<'a, 'b> Book<'a, 'b> {
             name: String::from(name),
             pages_count,
             authors,
+            isbn,
+            archived,
+            version,
+            changes: 0,
             publisher,
         }
     }
@@ -32,46 +45,159 @@ impl<'a, 'b> Book<'a, 'b> {
         name: &str,
         pages_count: i32,
         authors: Vec<i32>,
+        isbn: Option<Isbn>,
         publisher: &'a DomainEventPublisher<'b>,
-    ) -> Self {
-        assert!(!name.is_empty());
-        assert!(pages_count.is_positive());
-        assert!(!authors.is_empty());
+    ) -> Result<Self, Vec<DomainError>> {
+        Book::validate(name, pages_count, &authors)?;
 
-        let book = Self {
+        let mut book = Book::materialize(
             id,
-            name: String::from(name),
+            name,
             pages_count,
-            authors: authors.clone(),
+            authors.clone(),
+            isbn.clone(),
+            false,
+            0,
             publisher,
-        };
+        );
 
-        publisher.publish(&DomainEvent::BookCreated(BookCreated {
+        book.publish(DomainEvent::BookCreated(BookCreated {
             id,
             name: String::from(name),
             pages_count,
             authors,
+            isbn,
         }));
 
-        book
+        Ok(book)
+    }
+
+    pub fn from_history(
+        history: impl IntoIterator<Item = DomainEvent>,
+        publisher: &'a DomainEventPublisher<'b>,
+    ) -> Option<Self> {
+        let mut history = history.into_iter();
+        let mut book = match history.next()? {
+            DomainEvent::BookCreated(e) => Book::materialize(
+                e.id,
+                &e.name,
+                e.pages_count,
+                e.authors,
+                e.isbn,
+                false,
+                1,
+                publisher,
+            ),
+            _ => return None,
+        };
+
+        for e in history {
+            book.apply(e);
+            book.version += 1;
+        }
+
+        Some(book)
     }
 
-    pub fn update(&mut self, name: &str, pages_count: i32, authors: Vec<i32>) {
-        assert!(!name.is_empty());
-        assert!(pages_count.is_positive());
-        assert!(!authors.is_empty());
+    pub fn update(
+        &mut self,
+        name: &str,
+        pages_count: i32,
+        authors: Vec<i32>,
+    ) -> Result<(), Vec<DomainError>> {
+        self.validate_update(name, pages_count, &authors)?;
 
         if self.name != name {
-            self.name = String::from(name);
-            self.publisher
-                .publish(&DomainEvent::BookRenamed(BookRenamed {
-                    id: self.id,
-                    name: String::from(name),
-                }));
+            let previous_name = std::mem::replace(&mut self.name, String::from(name));
+            self.publish(DomainEvent::BookRenamed(BookRenamed {
+                id: self.id,
+                previous_name,
+                name: String::from(name),
+            }));
+        }
+
+        if self.pages_count != pages_count {
+            let previous_pages_count = std::mem::replace(&mut self.pages_count, pages_count);
+            self.publish(DomainEvent::BookPagesCountChanged(BookPagesCountChanged {
+                id: self.id,
+                previous_pages_count,
+                pages_count,
+            }));
         }
 
-        self.pages_count = pages_count;
-        self.authors = authors;
+        let previous_authors = std::mem::replace(&mut self.authors, authors);
+        let removed: Vec<i32> = previous_authors
+            .iter()
+            .filter(|a| !self.authors.contains(a))
+            .copied()
+            .collect();
+        let added: Vec<i32> = self
+            .authors
+            .iter()
+            .filter(|a| !previous_authors.contains(a))
+            .copied()
+            .collect();
+        for author_id in removed {
+            self.publish(DomainEvent::BookAuthorRemoved(BookAuthorRemoved {
+                id: self.id,
+                author_id,
+            }));
+        }
+        for author_id in added {
+            self.publish(DomainEvent::BookAuthorAdded(BookAuthorAdded {
+                id: self.id,
+                author_id,
+            }));
+        }
+
+        Ok(())
+    }
+
+    pub fn reassign_author(&mut self, from: i32, to: i32) -> Result<(), Vec<DomainError>> {
+        let mut authors = Vec::with_capacity(self.authors.len());
+        for author_id in self
+            .authors
+            .iter()
+            .map(|a| if *a == from { to } else { *a })
+        {
+            if !authors.contains(&author_id) {
+                authors.push(author_id);
+            }
+        }
+
+        let name = self.name.clone();
+        self.update(&name, self.pages_count, authors)
+    }
+
+    pub fn change_isbn(&mut self, isbn: Option<Isbn>) -> Result<(), Vec<DomainError>> {
+        if self.archived {
+            return Err(vec![DomainError::BookArchived(self.id)]);
+        }
+
+        if self.isbn != isbn {
+            let previous_isbn = std::mem::replace(&mut self.isbn, isbn.clone());
+            self.publish(DomainEvent::BookIsbnChanged(BookIsbnChanged {
+                id: self.id,
+                previous_isbn,
+                isbn,
+            }));
+        }
+
+        Ok(())
+    }
+
+    pub fn archive(&mut self) {
+        if !self.archived {
+            self.archived = true;
+            self.publish(DomainEvent::BookArchived(BookArchived { id: self.id }));
+        }
+    }
+
+    pub fn restore(&mut self) {
+        if self.archived {
+            self.archived = false;
+            self.publish(DomainEvent::BookRestored(BookRestored { id: self.id }));
+        }
     }
 
     pub fn id(&self) -> i32 {
@@ -89,26 +215,314 @@ impl<'a, 'b> Book<'a, 'b> {
     pub fn authors(&self) -> &[i32] {
         &self.authors
     }
+
+    pub fn isbn(&self) -> Option<&Isbn> {
+        self.isbn.as_ref()
+    }
+
+    pub fn archived(&self) -> bool {
+        self.archived
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn next_version(&self) -> i32 {
+        self.version + self.changes
+    }
+
+    fn publish(&mut self, e: DomainEvent) {
+        self.changes += 1;
+        self.publisher.publish(&e);
+    }
+
+    fn apply(&mut self, e: DomainEvent) {
+        match e {
+            DomainEvent::BookRenamed(e) => self.name = e.name,
+            DomainEvent::BookPagesCountChanged(e) => self.pages_count = e.pages_count,
+            DomainEvent::BookIsbnChanged(e) => self.isbn = e.isbn,
+            DomainEvent::BookAuthorAdded(e) => self.authors.push(e.author_id),
+            DomainEvent::BookAuthorRemoved(e) => self.authors.retain(|a| *a != e.author_id),
+            DomainEvent::BookArchived(_) => self.archived = true,
+            DomainEvent::BookRestored(_) => self.archived = false,
+            _ => {}
+        }
+    }
+
+    pub fn validate_update(
+        &self,
+        name: &str,
+        pages_count: i32,
+        authors: &[i32],
+    ) -> Result<(), Vec<DomainError>> {
+        if self.archived {
+            return Err(vec![DomainError::BookArchived(self.id)]);
+        }
+        Book::validate(name, pages_count, authors)
+    }
+
+    pub fn validate(name: &str, pages_count: i32, authors: &[i32]) -> Result<(), Vec<DomainError>> {
+        let mut errors = Vec::new();
+        if name.is_empty() {
+            errors.push(DomainError::EmptyBookName);
+        }
+        if !pages_count.is_positive() {
+            errors.push(DomainError::NonPositivePagesCount(pages_count));
+        }
+        if authors.is_empty() {
+            errors.push(DomainError::NoBookAuthors);
+        }
+        let mut duplicates: Vec<i32> = authors
+            .iter()
+            .enumerate()
+            .filter(|(i, a)| authors[..*i].contains(a))
+            .map(|(_, a)| *a)
+            .collect();
+        duplicates.sort_unstable();
+        duplicates.dedup();
+        if !duplicates.is_empty() {
+            errors.push(DomainError::DuplicateBookAuthors(duplicates));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 }
 
 #[async_trait]
 pub trait BookRepository<'a, 'b> {
     fn create(&self, book: &Book);
     fn update(&self, book: &Book);
+    fn archive(&self, book: &Book);
+    fn restore(&self, book: &Book);
     async fn next_identity(&self) -> i32;
-    async fn by_id(&self, id: i32) -> Option<Book<'a, 'b>>;
+    async fn by_id(&self, id: i32) -> Result<Option<Book<'a, 'b>>, Box<dyn Error + Send + Sync>>;
+    async fn id_by_isbn(&self, isbn: &Isbn) -> Option<i32>;
+    async fn active_by_author(
+        &self,
+        author_id: i32,
+    ) -> Result<Vec<Book<'a, 'b>>, Box<dyn Error + Send + Sync>>;
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BookCreated {
-    id: i32,
-    name: String,
-    pages_count: i32,
-    authors: Vec<i32>,
+    pub id: i32,
+    pub name: String,
+    pub pages_count: i32,
+    pub authors: Vec<i32>,
+    #[serde(default)]
+    pub isbn: Option<Isbn>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BookRenamed {
-    id: i32,
-    name: String,
+    pub id: i32,
+    pub previous_name: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookPagesCountChanged {
+    pub id: i32,
+    pub previous_pages_count: i32,
+    pub pages_count: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookIsbnChanged {
+    pub id: i32,
+    pub previous_isbn: Option<Isbn>,
+    pub isbn: Option<Isbn>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookAuthorAdded {
+    pub id: i32,
+    pub author_id: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookAuthorRemoved {
+    pub id: i32,
+    pub author_id: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookArchived {
+    pub id: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookRestored {
+    pub id: i32,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn new_collects_every_violation() {
+        let publisher = DomainEventPublisher::new();
+
+        let errors = Book::new(1, "", 0, vec![], None, &publisher).err().unwrap();
+
+        assert_eq!(
+            errors,
+            vec![
+                DomainError::EmptyBookName,
+                DomainError::NonPositivePagesCount(0),
+                DomainError::NoBookAuthors,
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_authors() {
+        let publisher = DomainEventPublisher::new();
+
+        let errors = Book::new(1, "book1", 100, vec![1, 2, 1, 1, 2], None, &publisher)
+            .err()
+            .unwrap();
+
+        assert_eq!(errors, vec![DomainError::DuplicateBookAuthors(vec![1, 2])]);
+    }
+
+    #[test]
+    fn update_rejects_invalid_state() {
+        let publisher = DomainEventPublisher::new();
+        let mut book = Book::materialize(1, "book1", 100, vec![1], None, false, 1, &publisher);
+
+        let errors = book.update("book1", -5, vec![1]).unwrap_err();
+
+        assert_eq!(errors, vec![DomainError::NonPositivePagesCount(-5)]);
+        assert_eq!(book.pages_count(), 100);
+    }
+
+    #[test]
+    fn update_publishes_one_event_per_change() {
+        let mut events = Vec::new();
+        {
+            let publisher = DomainEventPublisher::new();
+            publisher.subscribe(|e| events.push(e.domain_event_name()));
+
+            let mut book =
+                Book::materialize(1, "book1", 100, vec![1, 2], None, false, 1, &publisher);
+            book.update("book1", 100, vec![2, 1]).unwrap();
+            book.update("book1", 120, vec![2, 3]).unwrap();
+        }
+
+        assert_eq!(
+            events,
+            vec![
+                "book_pages_count_changed",
+                "book_author_removed",
+                "book_author_added"
+            ]
+        );
+    }
+
+    #[test]
+    fn archived_book_cannot_be_updated() {
+        let publisher = DomainEventPublisher::new();
+        let mut book = Book::materialize(1, "book1", 100, vec![1], None, false, 1, &publisher);
+
+        book.archive();
+        let errors = book.update("book2", 100, vec![1]).unwrap_err();
+
+        assert_eq!(errors, vec![DomainError::BookArchived(1)]);
+    }
+
+    #[test]
+    fn change_isbn() {
+        let mut events = Vec::new();
+        {
+            let publisher = DomainEventPublisher::new();
+            publisher.subscribe(|e| events.push(serde_json::to_value(e).unwrap()));
+
+            let isbn = Isbn::parse("9780306406157").unwrap();
+            let mut book =
+                Book::new(1, "book1", 100, vec![1], Some(isbn.clone()), &publisher).unwrap();
+            book.change_isbn(Some(isbn)).unwrap();
+            book.change_isbn(None).unwrap();
+        }
+
+        assert_eq!(events[0]["BookCreated"]["isbn"], "9780306406157");
+        assert_eq!(
+            events[1],
+            serde_json::json!({
+                "BookIsbnChanged": {"id": 1, "previous_isbn": "9780306406157", "isbn": null}
+            })
+        );
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn created_without_isbn_field() {
+        let e: DomainEvent = serde_json::from_str(
+            r#"{"BookCreated": {"id": 1, "name": "book1", "pages_count": 100, "authors": [1]}}"#,
+        )
+        .unwrap();
+
+        let publisher = DomainEventPublisher::new();
+        let book = Book::from_history([e], &publisher).unwrap();
+        assert_eq!(book.isbn(), None);
+    }
+
+    #[test]
+    fn reassign_author() {
+        let publisher = DomainEventPublisher::new();
+        let mut book =
+            Book::materialize(1, "book1", 100, vec![1, 2, 3], None, false, 1, &publisher);
+
+        book.reassign_author(1, 3).unwrap();
+
+        assert_eq!(book.authors(), vec![3, 2]);
+    }
+
+    #[test]
+    fn from_history() {
+        let mut history = Vec::new();
+        {
+            let publisher = DomainEventPublisher::new();
+            publisher.subscribe(|e| history.push(serde_json::to_string(e).unwrap()));
+
+            let mut book = Book::new(1, "book1", 100, vec![1, 2], None, &publisher).unwrap();
+            book.update("book1-renamed", 120, vec![2, 3]).unwrap();
+            book.change_isbn(Some(Isbn::parse("0-306-40615-2").unwrap()))
+                .unwrap();
+            book.archive();
+        }
+
+        let publisher = DomainEventPublisher::new();
+        let book = Book::from_history(
+            history.iter().map(|e| serde_json::from_str(e).unwrap()),
+            &publisher,
+        )
+        .unwrap();
+
+        assert_eq!(book.id(), 1);
+        assert_eq!(book.name(), "book1-renamed");
+        assert_eq!(book.pages_count(), 120);
+        assert_eq!(book.authors(), vec![2, 3]);
+        assert_eq!(book.isbn().unwrap().as_str(), "9780306406157");
+        assert!(book.archived());
+    }
+
+    #[test]
+    fn next_version_counts_published_events() {
+        let publisher = DomainEventPublisher::new();
+        let book = Book::new(1, "book1", 100, vec![1], None, &publisher).unwrap();
+        assert_eq!(book.version(), 0);
+        assert_eq!(book.next_version(), 1);
+
+        let mut book = Book::materialize(1, "book1", 100, vec![1], None, false, 3, &publisher);
+        book.update("book1-renamed", 120, vec![1]).unwrap();
+        assert_eq!(book.version(), 3);
+        assert_eq!(book.next_version(), 5);
+    }
 }
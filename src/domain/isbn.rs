@@ -0,0 +1,222 @@
+use super::DomainError;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+// Kept as the 13 digits of the ISBN-13 form, ISBN-10 input is converted on parse.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Isbn(String);
+
+impl Isbn {
+    pub fn parse(value: &str) -> Result<Self, DomainError> {
+        let invalid = || DomainError::InvalidIsbn(String::from(value));
+        let compact: String = value
+            .chars()
+            .filter(|c| *c != '-' && *c != ' ')
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        // Checked up front so that lengths count characters and splitting stays on ASCII.
+        if !compact.chars().all(|c| c.is_ascii_digit() || c == 'X') {
+            return Err(invalid());
+        }
+
+        match compact.len() {
+            10 => {
+                let (body, check) = compact.split_at(9);
+                if !body.chars().all(|c| c.is_ascii_digit())
+                    || check.chars().ne(check10(body).chars())
+                {
+                    return Err(invalid());
+                }
+                let body = format!("978{}", body);
+                let check = check13(&body);
+                Ok(Isbn(format!("{}{}", body, check)))
+            }
+            13 => {
+                let (body, check) = compact.split_at(12);
+                if !compact.chars().all(|c| c.is_ascii_digit())
+                    || !(body.starts_with("978") || body.starts_with("979"))
+                    || check.chars().ne(check13(body).chars())
+                {
+                    return Err(invalid());
+                }
+                Ok(Isbn(compact))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn to_isbn13(&self) -> String {
+        self.0.clone()
+    }
+
+    // Only 978 ISBNs have an ISBN-10 form.
+    pub fn to_isbn10(&self) -> Option<String> {
+        let body = self.0.strip_prefix("978")?.get(..9)?;
+        Some(format!("{}{}", body, check10(body)))
+    }
+
+    // Falls back to the plain digits where the registrant ranges are not known.
+    pub fn hyphenated(&self) -> String {
+        self.0
+            .strip_prefix("978")
+            .and_then(hyphenate)
+            .map_or_else(|| self.0.clone(), |rest| format!("978-{}", rest))
+    }
+
+    pub fn hyphenated_isbn10(&self) -> Option<String> {
+        self.to_isbn10()
+            .map(|isbn| hyphenate(&isbn).unwrap_or(isbn))
+    }
+}
+
+impl fmt::Display for Isbn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.hyphenated())
+    }
+}
+
+impl FromStr for Isbn {
+    type Err = DomainError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Isbn::parse(s)
+    }
+}
+
+impl TryFrom<String> for Isbn {
+    type Error = DomainError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Isbn::parse(&value)
+    }
+}
+
+impl From<Isbn> for String {
+    fn from(isbn: Isbn) -> Self {
+        isbn.0
+    }
+}
+
+fn check10(body: &str) -> String {
+    let sum: u32 = body
+        .chars()
+        .zip((2..=10).rev())
+        .map(|(c, weight)| c.to_digit(10).unwrap() * weight)
+        .sum();
+    match (11 - sum % 11) % 11 {
+        10 => String::from("X"),
+        check => check.to_string(),
+    }
+}
+
+fn check13(body: &str) -> String {
+    let sum: u32 = body
+        .chars()
+        .enumerate()
+        .map(|(i, c)| c.to_digit(10).unwrap() * if i % 2 == 0 { 1 } else { 3 })
+        .sum();
+    ((10 - sum % 10) % 10).to_string()
+}
+
+// Splits an ISBN-10 into group, registrant, publication and check digit. Registrant lengths
+// are only known for the English-language groups 0 and 1, other groups are left alone.
+fn hyphenate(isbn10: &str) -> Option<String> {
+    let (group, rest) = isbn10.split_at(1);
+    let (body, check) = rest.split_at(8);
+    let ranges: &[(u32, usize)] = match group {
+        "0" => &[
+            (1999999, 2),
+            (6999999, 3),
+            (8499999, 4),
+            (8999999, 5),
+            (9499999, 6),
+            (9999999, 7),
+        ],
+        "1" => &[
+            (999999, 2),
+            (3999999, 3),
+            (5499999, 4),
+            (8697999, 5),
+            (9989999, 6),
+            (9999999, 7),
+        ],
+        _ => return None,
+    };
+
+    let key: u32 = body[..7].parse().unwrap();
+    let length = ranges
+        .iter()
+        .find(|(max, _)| key <= *max)
+        .map_or(7, |(_, length)| *length);
+    let (registrant, publication) = body.split_at(length);
+    Some(format!(
+        "{}-{}-{}-{}",
+        group, registrant, publication, check
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let isbn = Isbn::parse("0-306-40615-2").unwrap();
+        assert_eq!(isbn.as_str(), "9780306406157");
+        assert_eq!(isbn, Isbn::parse("978-0-306-40615-7").unwrap());
+        assert_eq!(isbn, Isbn::parse("978 0306406157").unwrap());
+        assert_eq!(Isbn::parse("080442957X").unwrap().as_str(), "9780804429573");
+        assert_eq!(Isbn::parse("080442957x").unwrap().as_str(), "9780804429573");
+
+        for invalid in [
+            "0-306-40615-3",
+            "9780306406158",
+            "12345",
+            "97803064061X7",
+            "9770306406156",
+            "030640615é",
+            "ä0306406152",
+            "０３０６４０６１５２",
+        ] {
+            assert_eq!(
+                Isbn::parse(invalid),
+                Err(DomainError::InvalidIsbn(String::from(invalid)))
+            );
+        }
+    }
+
+    #[test]
+    fn convert_and_format() {
+        let isbn = Isbn::parse("9780306406157").unwrap();
+        assert_eq!(isbn.to_isbn10().unwrap(), "0306406152");
+        assert_eq!(isbn.hyphenated(), "978-0-306-40615-7");
+        assert_eq!(isbn.hyphenated_isbn10().unwrap(), "0-306-40615-2");
+        assert_eq!(
+            Isbn::parse("9780804429573").unwrap().to_isbn10().unwrap(),
+            "080442957X"
+        );
+        assert_eq!(
+            Isbn::parse("9781593278281").unwrap().to_string(),
+            "978-1-59327-828-1"
+        );
+
+        let isbn = Isbn::parse("9791032305690").unwrap();
+        assert_eq!(isbn.to_isbn10(), None);
+        assert_eq!(isbn.hyphenated(), "9791032305690");
+        let isbn = Isbn::parse("9782070360024").unwrap();
+        assert_eq!(isbn.hyphenated(), "9782070360024");
+        assert_eq!(isbn.hyphenated_isbn10().unwrap(), "2070360024");
+    }
+
+    #[test]
+    fn serde() {
+        let isbn: Isbn = serde_json::from_str("\"0-306-40615-2\"").unwrap();
+        assert_eq!(serde_json::to_string(&isbn).unwrap(), "\"9780306406157\"");
+        assert!(serde_json::from_str::<Isbn>("\"0-306-40615-3\"").is_err());
+    }
+}
@@ -1,42 +1,134 @@
 pub mod author;
+pub mod author_listing;
 pub mod book;
+pub mod book_listing;
+pub mod catalog;
+pub mod event_sourced;
+pub mod export;
+pub mod import;
+#[cfg(any(test, feature = "in-memory"))]
+pub mod in_memory;
+pub mod projection;
+pub mod relay;
+pub mod search;
 
 use async_trait::async_trait;
-use sqlx::{Executor, PgPool};
+use sqlx::{
+    postgres::{PgArguments, PgRow},
+    Arguments, Encode, PgPool, Postgres, Row, Type,
+};
 use std::sync::RwLock;
 
 use crate::{
-    application::{EventStore, StoredEvent, UoW},
-    domain::DomainEvent,
+    application::{BoxError, CommitError, EventStore, Position, StoredEvent, UoW},
+    domain::{Aggregate, DomainEvent},
 };
 
+pub struct Statement {
+    sql: &'static str,
+    arguments: PgArguments,
+    expected_rows: Option<u64>,
+}
+
+impl Statement {
+    pub fn new(sql: &'static str) -> Self {
+        Self {
+            sql,
+            arguments: PgArguments::default(),
+            expected_rows: None,
+        }
+    }
+
+    pub fn expect_rows(mut self, rows: u64) -> Self {
+        self.expected_rows = Some(rows);
+        self
+    }
+
+    pub fn bind<'q, T>(mut self, value: T) -> Self
+    where
+        T: 'q + Send + Encode<'q, Postgres> + Type<Postgres>,
+    {
+        self.arguments.add(value);
+        self
+    }
+}
+
 pub struct DbUoW {
     pool: PgPool,
-    queries: RwLock<Vec<String>>,
+    statements: RwLock<Vec<Statement>>,
 }
 
 impl DbUoW {
     pub fn new(pool: PgPool) -> Self {
         Self {
             pool,
-            queries: RwLock::new(Vec::new()),
+            statements: RwLock::new(Vec::new()),
         }
     }
 
-    pub fn add(&self, query: String) {
-        self.queries.write().unwrap().push(query);
+    pub fn add(&self, statement: Statement) {
+        self.statements.write().unwrap().push(statement);
     }
 }
 
 #[async_trait]
 impl UoW for DbUoW {
-    async fn commit(&self) {
-        let sql = self.queries.read().unwrap().join(";");
-        self.pool.execute(&*sql).await.unwrap();
-        self.queries.write().unwrap().clear();
+    async fn commit(&self) -> Result<(), CommitError> {
+        let statements = std::mem::take(&mut *self.statements.write().unwrap());
+
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| CommitError::Begin(e.into()))?;
+
+        for (index, statement) in statements.into_iter().enumerate() {
+            let Statement {
+                sql,
+                arguments,
+                expected_rows,
+            } = statement;
+
+            let error = match sqlx::query_with(sql, arguments).execute(&mut *tx).await {
+                Ok(result) => match expected_rows {
+                    Some(rows) if rows != result.rows_affected() => CommitError::Conflict {
+                        index,
+                        sql: String::from(sql),
+                    },
+                    _ => continue,
+                },
+                Err(e) if is_conflict(&e) => CommitError::Conflict {
+                    index,
+                    sql: String::from(sql),
+                },
+                Err(e) => CommitError::Statement {
+                    index,
+                    sql: String::from(sql),
+                    source: e.into(),
+                },
+            };
+
+            let _ = tx.rollback().await;
+            return Err(error);
+        }
+
+        tx.commit().await.map_err(|e| CommitError::Commit(e.into()))
+    }
+
+    fn rollback(&self) {
+        self.statements.write().unwrap().clear();
     }
 }
 
+// Unique indexes that only a concurrent writer can hit, as the services check them before:
+// an event stream sequence, or an ISBN another book took meanwhile.
+fn is_conflict(e: &sqlx::Error) -> bool {
+    matches!(
+        e.as_database_error().and_then(|e| e.constraint()),
+        Some("stored_event_stream_idx" | "book_isbn")
+    )
+}
+
 pub struct DbEventStore<'a> {
     db: &'a DbUoW,
 }
@@ -47,36 +139,276 @@ impl<'a> DbEventStore<'a> {
     }
 }
 
+#[async_trait]
 impl<'a> EventStore for DbEventStore<'a> {
     fn append(&mut self, domain_event: &DomainEvent) {
-        let name = domain_event.domain_event_name();
+        let (aggregate, id) = domain_event.stream();
         let payload = serde_json::to_string(domain_event).expect("domain_event serialized");
-        let stored_event = StoredEvent::new(name, &payload);
-        let sql = format!(
-            "insert into stored_event(name, payload) values('{}', '{}')",
-            stored_event.name(),
-            stored_event.playload()
+
+        self.db.add(
+            Statement::new(
+                "insert into stored_event(aggregate_type, aggregate_id, sequence, name, payload) \
+                values($1, $2, (select coalesce(max(sequence), 0) + 1 from stored_event where aggregate_type = $1 and aggregate_id = $2), $3, $4)",
+            )
+            .bind(aggregate.name())
+            .bind(id)
+            .bind(domain_event.domain_event_name())
+            .bind(payload),
         );
+    }
 
-        self.db.add(sql);
+    async fn load_stream(
+        &self,
+        aggregate: Aggregate,
+        id: i32,
+    ) -> Result<Vec<StoredEvent>, BoxError> {
+        let events = sqlx::query(
+            "select * from stored_event where aggregate_type = $1 and aggregate_id = $2 order by sequence",
+        )
+        .bind(aggregate.name())
+        .bind(id)
+        .map(|row: PgRow| stored_event(&row))
+        .fetch_all(&self.db.pool)
+        .await?;
+        Ok(events)
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use crate::domain::book::BookCreated;
+// Only events of transactions older than every one still running are returned, so nothing
+// can later commit in front of the last event read. A long transaction holds back reading
+// until it ends.
+const SETTLED: &str = "transaction_id < pg_snapshot_xmin(pg_current_snapshot())::text::bigint";
 
-//     use super::*;
+pub async fn events_after(
+    pool: &PgPool,
+    position: Position,
+    limit: i64,
+) -> Result<Vec<StoredEvent>, sqlx::Error> {
+    select_events_after(pool, position, SETTLED, limit).await
+}
 
-//     #[sqlx::test]
-//     fn stored_event_test(pool: PgPool) {
-//         let uow = DbUoW::new(pool);
-//         let mut event_store = DbEventStore::new(&uow);
+// Every event committed so far, including those an earlier transaction may still commit in
+// front of; for readers that must not miss what others have already seen.
+pub async fn committed_events_after(
+    pool: &PgPool,
+    position: Position,
+    limit: i64,
+) -> Result<Vec<StoredEvent>, sqlx::Error> {
+    select_events_after(pool, position, "true", limit).await
+}
+
+async fn select_events_after(
+    pool: &PgPool,
+    position: Position,
+    filter: &str,
+    limit: i64,
+) -> Result<Vec<StoredEvent>, sqlx::Error> {
+    sqlx::query(&format!(
+        "select * from stored_event where (transaction_id, id) > ($1, $2) and {} \
+        order by transaction_id, id limit $3",
+        filter
+    ))
+    .bind(position.transaction_id)
+    .bind(position.event_id)
+    .bind(limit)
+    .map(|row: PgRow| stored_event(&row))
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn count_events_after(pool: &PgPool, position: Position) -> Result<i64, sqlx::Error> {
+    let (count,): (i64,) = sqlx::query_as(&format!(
+        "select count(*) from stored_event where (transaction_id, id) > ($1, $2) and {}",
+        SETTLED
+    ))
+    .bind(position.transaction_id)
+    .bind(position.event_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+pub async fn latest_events(pool: &PgPool, limit: i64) -> Result<Vec<StoredEvent>, sqlx::Error> {
+    sqlx::query(&format!(
+        "select * from (select * from stored_event where {} order by transaction_id desc, id desc limit $1) e \
+        order by transaction_id, id",
+        SETTLED
+    ))
+    .bind(limit)
+    .map(|row: PgRow| stored_event(&row))
+    .fetch_all(pool)
+    .await
+}
 
-//         event_store.append(&DomainEvent::BookCreated(BookCreated {
-//             name: String::from("book1"),
-//         }));
+fn stored_event(row: &PgRow) -> StoredEvent {
+    StoredEvent::new(
+        row.get("id"),
+        row.get("transaction_id"),
+        row.get("aggregate_type"),
+        row.get("aggregate_id"),
+        row.get("sequence"),
+        row.get("name"),
+        row.get("payload"),
+    )
+}
 
-//         uow.commit().await;
-//     }
-// }
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::{author::Author, book::Book, DomainEventPublisher};
+    use sqlx::Executor;
+
+    #[sqlx::test]
+    async fn append(pool: PgPool) {
+        let uow = DbUoW::new(pool);
+        let mut event_store = DbEventStore::new(&uow);
+
+        {
+            let publisher = DomainEventPublisher::new();
+            publisher.subscribe(|e| event_store.append(e));
+            Author::new(1, "Flann", "O'Brien", &publisher).unwrap();
+        }
+
+        uow.commit().await.unwrap();
+
+        let rows = sqlx::query("select * from stored_event")
+            .fetch_all(&uow.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get::<&str, _>("name"), "author_created");
+        assert!(rows[0].get::<&str, _>("payload").contains("O'Brien"));
+    }
+
+    #[sqlx::test]
+    async fn commit_failure(pool: PgPool) {
+        let uow = DbUoW::new(pool);
+        uow.add(Statement::new(
+            "insert into author(id, first_name, last_name, full_name) values(1, 'f', 'l', 'f l')",
+        ));
+        uow.add(Statement::new("insert into missing_table(id) values(1)"));
+
+        let result = uow.commit().await;
+
+        assert!(matches!(
+            result,
+            Err(CommitError::Statement { index: 1, .. })
+        ));
+        assert!(uow.statements.read().unwrap().is_empty());
+
+        let rows = sqlx::query("select * from author")
+            .fetch_all(&uow.pool)
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn rollback(pool: PgPool) {
+        let uow = DbUoW::new(pool);
+        uow.add(Statement::new(
+            "insert into author(id, first_name, last_name, full_name) values(1, 'f', 'l', 'f l')",
+        ));
+
+        uow.rollback();
+        uow.commit().await.unwrap();
+
+        let rows = sqlx::query("select * from author")
+            .fetch_all(&uow.pool)
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn author_renamed_events_migration(pool: PgPool) {
+        sqlx::query(
+            r#"insert into stored_event(aggregate_type, aggregate_id, sequence, name, payload) values
+            ('author', 1, 1, 'author_created', '{"AuthorCreated":{"id":1,"first_name":"f1","last_name":"l1","full_name":"f1 l1"}}'),
+            ('book', 1, 1, 'book_created', '{"BookCreated":{"id":1,"name":"book1","pages_count":100,"authors":[1]}}'),
+            ('author', 1, 2, 'author_created', '{"AuthorCreated":{"id":1,"first_name":"f2","last_name":"l1","full_name":"f2 l1"}}'),
+            ('book', 1, 2, 'book_renamed', '{"BookRenamed":{"id":1,"name":"book2"}}')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        pool.execute(include_str!(
+            "../migrations/20231007183512_author_renamed_events.sql"
+        ))
+        .await
+        .unwrap();
+
+        let rows = sqlx::query("select * from stored_event order by id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let events: Vec<(&str, serde_json::Value)> = rows
+            .iter()
+            .map(|r| {
+                (
+                    r.get("name"),
+                    serde_json::from_str(r.get("payload")).unwrap(),
+                )
+            })
+            .collect();
+
+        assert_eq!(events[0].0, "author_created");
+        assert_eq!(events[2].0, "author_renamed");
+        assert_eq!(
+            events[2].1,
+            serde_json::json!({
+                "AuthorRenamed": {
+                    "id": 1,
+                    "previous_first_name": "f1",
+                    "previous_last_name": "l1",
+                    "previous_full_name": "f1 l1",
+                    "first_name": "f2",
+                    "last_name": "l1",
+                    "full_name": "f2 l1",
+                }
+            })
+        );
+        assert_eq!(
+            events[3].1,
+            serde_json::json!({
+                "BookRenamed": { "id": 1, "previous_name": "book1", "name": "book2" }
+            })
+        );
+    }
+
+    #[sqlx::test]
+    async fn load_stream(pool: PgPool) {
+        let uow = DbUoW::new(pool);
+        let mut event_store = DbEventStore::new(&uow);
+
+        {
+            let publisher = DomainEventPublisher::new();
+            publisher.subscribe(|e| event_store.append(e));
+
+            let mut book = Book::new(2, "book2", 100, vec![1], None, &publisher).unwrap();
+            Author::new(3, "f3", "l3", &publisher).unwrap();
+            book.update("book2-renamed", 120, vec![1]).unwrap();
+        }
+
+        uow.commit().await.unwrap();
+
+        let events = event_store.load_stream(Aggregate::Book, 2).await.unwrap();
+        let events: Vec<(i32, &str)> = events.iter().map(|e| (e.sequence(), e.name())).collect();
+
+        assert_eq!(
+            events,
+            vec![
+                (1, "book_created"),
+                (2, "book_renamed"),
+                (3, "book_pages_count_changed")
+            ]
+        );
+        assert!(event_store
+            .load_stream(Aggregate::Book, 1)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
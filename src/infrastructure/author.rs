@@ -1,7 +1,10 @@
-use super::DbUoW;
-use crate::domain::{
-    author::{Author, AuthorRepository},
-    DomainEventPublisher,
+use super::{DbUoW, Statement};
+use crate::{
+    application::BoxError,
+    domain::{
+        author::{Author, AuthorRepository},
+        DomainEventPublisher,
+    },
 };
 use async_trait::async_trait;
 use sqlx::{postgres::PgRow, Row};
@@ -20,25 +23,55 @@ impl<'a, 'b, 'c> DbAuthorRepository<'a, 'b, 'c> {
 #[async_trait]
 impl<'a, 'b, 'c> AuthorRepository<'b, 'c> for DbAuthorRepository<'a, 'b, 'c> {
     fn create(&self, author: &Author) {
-        let sql = format!(
-            "insert into author(id, first_name, last_name, full_name) values ({}, '{}', '{}', '{}')",
-            author.id(),
-            author.first_name(),
-            author.last_name(),
-            author.full_name(),
+        self.db.add(
+            Statement::new(
+                "insert into author(id, first_name, last_name, full_name, version) values ($1, $2, $3, $4, $5)",
+            )
+            .bind(author.id())
+            .bind(author.first_name())
+            .bind(author.last_name())
+            .bind(author.full_name())
+            .bind(author.next_version()),
         );
-        self.db.add(sql);
     }
 
     fn update(&self, author: &Author) {
-        let sql = format!(
-            "update author set first_name = '{}', last_name = '{}', full_name = '{}' where id = {}",
-            author.first_name(),
-            author.last_name(),
-            author.full_name(),
-            author.id(),
+        self.db.add(
+            Statement::new(
+                "update author set first_name = $2, last_name = $3, full_name = $4, version = $6 where id = $1 and version = $5",
+            )
+            .bind(author.id())
+            .bind(author.first_name())
+            .bind(author.last_name())
+            .bind(author.full_name())
+            .bind(author.version())
+            .bind(author.next_version())
+            .expect_rows(1),
+        );
+    }
+
+    fn archive(&self, author: &Author) {
+        self.db.add(
+            Statement::new(
+                "update author set archived = true, version = $3 where id = $1 and version = $2",
+            )
+            .bind(author.id())
+            .bind(author.version())
+            .bind(author.next_version())
+            .expect_rows(1),
+        );
+    }
+
+    fn restore(&self, author: &Author) {
+        self.db.add(
+            Statement::new(
+                "update author set archived = false, version = $3 where id = $1 and version = $2",
+            )
+            .bind(author.id())
+            .bind(author.version())
+            .bind(author.next_version())
+            .expect_rows(1),
         );
-        self.db.add(sql);
     }
 
     async fn next_identity(&self) -> i32 {
@@ -49,7 +82,7 @@ impl<'a, 'b, 'c> AuthorRepository<'b, 'c> for DbAuthorRepository<'a, 'b, 'c> {
         id.0 as i32
     }
 
-    async fn by_id(&self, id: i32) -> Option<Author<'b, 'c>> {
+    async fn by_id(&self, id: i32) -> Result<Option<Author<'b, 'c>>, BoxError> {
         let author = sqlx::query("select * from author where id = $1")
             .bind(id)
             .map(|row: PgRow| {
@@ -58,13 +91,14 @@ impl<'a, 'b, 'c> AuthorRepository<'b, 'c> for DbAuthorRepository<'a, 'b, 'c> {
                     row.get("first_name"),
                     row.get("last_name"),
                     row.get("full_name"),
+                    row.get("archived"),
+                    row.get("version"),
                     self.publisher,
                 )
             })
             .fetch_optional(&self.db.pool)
-            .await
-            .unwrap();
-        author
+            .await?;
+        Ok(author)
     }
 }
 
@@ -97,10 +131,10 @@ mod test {
         let repo = DbAuthorRepository::new(&uow, &publisher);
 
         let author_id = 10;
-        let author = Author::materialize(author_id, "f", "l", "full", &publisher);
+        let author = Author::materialize(author_id, "f", "l", "full", false, 0, &publisher);
         repo.create(&author);
 
-        uow.commit().await;
+        uow.commit().await.unwrap();
 
         let rows = sqlx::query("select * from author where id = $1")
             .bind(author_id)
@@ -115,6 +149,36 @@ mod test {
         assert_eq!(rows[0].get::<&str, _>("full_name"), "full");
     }
 
+    #[sqlx::test(fixtures("author"))]
+    fn create_with_quotes(pool: PgPool) {
+        let uow = DbUoW::new(pool);
+        let publisher = DomainEventPublisher::new();
+        let repo = DbAuthorRepository::new(&uow, &publisher);
+
+        let author_id = 10;
+        let author = Author::materialize(
+            author_id,
+            "Flann",
+            "O'Brien",
+            "Flann O'Brien",
+            false,
+            0,
+            &publisher,
+        );
+        repo.create(&author);
+
+        uow.commit().await.unwrap();
+
+        let row = sqlx::query("select * from author where id = $1")
+            .bind(author_id)
+            .fetch_one(&uow.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(row.get::<&str, _>("last_name"), "O'Brien");
+        assert_eq!(row.get::<&str, _>("full_name"), "Flann O'Brien");
+    }
+
     #[sqlx::test(fixtures("author"))]
     fn update(pool: PgPool) {
         let uow = DbUoW::new(pool);
@@ -127,11 +191,13 @@ mod test {
             "f1-renamed",
             "l1-renamed",
             "full-renamed",
+            false,
+            0,
             &publisher,
         );
         repo.update(&author);
 
-        uow.commit().await;
+        uow.commit().await.unwrap();
 
         let rows = sqlx::query("select * from author where id = $1")
             .bind(author_id)
@@ -153,7 +219,7 @@ mod test {
         let repo = DbAuthorRepository::new(&uow, &publisher);
 
         let author_id = 1;
-        let author = repo.by_id(author_id).await;
+        let author = repo.by_id(author_id).await.unwrap();
         assert!(author.is_some());
 
         let author = author.unwrap();
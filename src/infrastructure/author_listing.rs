@@ -0,0 +1,61 @@
+use crate::application::author_listing::{AuthorListItem, AuthorListing};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, PgPool, Row};
+
+pub struct DbAuthorListing {
+    pool: PgPool,
+}
+
+impl DbAuthorListing {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl AuthorListing for DbAuthorListing {
+    async fn list(&self) -> Vec<AuthorListItem> {
+        sqlx::query("select * from author where not archived order by id")
+            .map(|row: PgRow| author_list_item(&row))
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+    }
+
+    async fn by_id(&self, id: i32) -> Option<AuthorListItem> {
+        sqlx::query("select * from author where id = $1")
+            .bind(id)
+            .map(|row: PgRow| author_list_item(&row))
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap()
+    }
+}
+
+fn author_list_item(row: &PgRow) -> AuthorListItem {
+    AuthorListItem {
+        id: row.get("id"),
+        first_name: row.get("first_name"),
+        last_name: row.get("last_name"),
+        full_name: row.get("full_name"),
+        archived: row.get("archived"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[sqlx::test(fixtures("author"))]
+    async fn list(pool: PgPool) {
+        sqlx::query("update author set archived = true where id = 2")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let listing = DbAuthorListing::new(pool);
+
+        let ids: Vec<i32> = listing.list().await.iter().map(|a| a.id).collect();
+        assert_eq!(ids, vec![1]);
+        assert!(listing.by_id(2).await.unwrap().archived);
+    }
+}
@@ -1,7 +1,11 @@
-use super::DbUoW;
-use crate::domain::{
-    book::{Book, BookRepository},
-    DomainEventPublisher,
+use super::{DbUoW, Statement};
+use crate::{
+    application::BoxError,
+    domain::{
+        book::{Book, BookRepository},
+        isbn::Isbn,
+        DomainEventPublisher,
+    },
 };
 use async_trait::async_trait;
 use sqlx::Row;
@@ -20,46 +24,76 @@ impl<'a, 'b, 'c> DbBookRepository<'a, 'b, 'c> {
 #[async_trait]
 impl<'a, 'b, 'c> BookRepository<'b, 'c> for DbBookRepository<'a, 'b, 'c> {
     fn create(&self, book: &Book) {
-        let sql = format!(
-            "insert into book(id, name, pages_count) values ({}, '{}', {})",
-            book.id(),
-            book.name(),
-            book.pages_count()
+        self.db.add(
+            Statement::new(
+                "insert into book(id, name, pages_count, version, isbn) values ($1, $2, $3, $4, $5)",
+            )
+            .bind(book.id())
+            .bind(book.name())
+            .bind(book.pages_count())
+            .bind(book.next_version())
+            .bind(book.isbn().map(Isbn::to_isbn13)),
         );
-        self.db.add(sql);
 
         for author in book.authors() {
-            let sql = format!(
-                "insert into author_book(author_id, book_id) values ({}, {})",
-                author,
-                book.id()
+            self.db.add(
+                Statement::new("insert into author_book(author_id, book_id) values ($1, $2)")
+                    .bind(*author)
+                    .bind(book.id()),
             );
-            self.db.add(sql);
         }
     }
 
     fn update(&self, book: &Book) {
-        let sql = format!(
-            "update book set name = '{}', pages_count = {} where id = {}",
-            book.name(),
-            book.pages_count(),
-            book.id()
+        self.db.add(
+            Statement::new(
+                "update book set name = $2, pages_count = $3, version = $5, isbn = $6 where id = $1 and version = $4",
+            )
+            .bind(book.id())
+            .bind(book.name())
+            .bind(book.pages_count())
+            .bind(book.version())
+            .bind(book.next_version())
+            .bind(book.isbn().map(Isbn::to_isbn13))
+            .expect_rows(1),
         );
-        self.db.add(sql);
 
-        let sql = format!("delete from author_book where book_id = {}", book.id());
-        self.db.add(sql);
+        self.db
+            .add(Statement::new("delete from author_book where book_id = $1").bind(book.id()));
 
         for author in book.authors() {
-            let sql = format!(
-                "insert into author_book(author_id, book_id) values ({}, {})",
-                author,
-                book.id(),
+            self.db.add(
+                Statement::new("insert into author_book(author_id, book_id) values ($1, $2)")
+                    .bind(*author)
+                    .bind(book.id()),
             );
-            self.db.add(sql);
         }
     }
 
+    fn archive(&self, book: &Book) {
+        self.db.add(
+            Statement::new(
+                "update book set archived = true, version = $3 where id = $1 and version = $2",
+            )
+            .bind(book.id())
+            .bind(book.version())
+            .bind(book.next_version())
+            .expect_rows(1),
+        );
+    }
+
+    fn restore(&self, book: &Book) {
+        self.db.add(
+            Statement::new(
+                "update book set archived = false, version = $3 where id = $1 and version = $2",
+            )
+            .bind(book.id())
+            .bind(book.version())
+            .bind(book.next_version())
+            .expect_rows(1),
+        );
+    }
+
     async fn next_identity(&self) -> i32 {
         // let id: (i64,) = sqlx::query_as("select nextval(pg_get_serial_sequence('book', 'id'))")
         let id: (i64,) = sqlx::query_as("select nextval('book_id_seq')")
@@ -69,36 +103,63 @@ impl<'a, 'b, 'c> BookRepository<'b, 'c> for DbBookRepository<'a, 'b, 'c> {
         id.0 as i32
     }
 
-    async fn by_id(&self, id: i32) -> Option<Book<'b, 'c>> {
+    async fn by_id(&self, id: i32) -> Result<Option<Book<'b, 'c>>, BoxError> {
         let rows = sqlx::query(
             "select * from book inner join author_book on author_book.book_id = id where id = $1",
         )
         .bind(id)
         .fetch_all(&self.db.pool)
-        .await
-        .unwrap();
+        .await?;
+
+        let Some(row) = rows.first() else {
+            return Ok(None);
+        };
+        let authors = rows.iter().map(|r| r.get("author_id")).collect();
+        let isbn = row
+            .get::<Option<&str>, _>("isbn")
+            .map(Isbn::parse)
+            .transpose()?;
+        Ok(Some(Book::materialize(
+            row.get("id"),
+            row.get("name"),
+            row.get("pages_count"),
+            authors,
+            isbn,
+            row.get("archived"),
+            row.get("version"),
+            self.publisher,
+        )))
+    }
 
-        if rows.is_empty() {
-            None
-        } else {
-            let authors = rows.iter().map(|r| r.get("author_id")).collect();
-            rows.first().map(|row| {
-                Book::materialize(
-                    row.get("id"),
-                    row.get("name"),
-                    row.get("pages_count"),
-                    authors,
-                    self.publisher,
-                )
-            })
+    async fn id_by_isbn(&self, isbn: &Isbn) -> Option<i32> {
+        let id: Option<(i32,)> = sqlx::query_as("select id from book where isbn = $1")
+            .bind(isbn.as_str())
+            .fetch_optional(&self.db.pool)
+            .await
+            .unwrap();
+        id.map(|(id,)| id)
+    }
+
+    async fn active_by_author(&self, author_id: i32) -> Result<Vec<Book<'b, 'c>>, BoxError> {
+        let ids: Vec<(i32,)> = sqlx::query_as(
+            "select id from book inner join author_book on author_book.book_id = id where author_id = $1 and not archived order by id",
+        )
+        .bind(author_id)
+        .fetch_all(&self.db.pool)
+        .await?;
+
+        let mut books = Vec::with_capacity(ids.len());
+        for (id,) in ids {
+            books.extend(self.by_id(id).await?);
         }
+        Ok(books)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::application::UoW;
+    use crate::application::{CommitError, UoW};
     use sqlx::PgPool;
 
     #[sqlx::test(fixtures("book"))]
@@ -124,10 +185,19 @@ mod test {
         let repo = DbBookRepository::new(&uow, &publisher);
 
         let book_id = 10;
-        let book = Book::materialize(book_id, "book10", 100, vec![1, 2], &publisher);
+        let book = Book::materialize(
+            book_id,
+            "book10",
+            100,
+            vec![1, 2],
+            None,
+            false,
+            0,
+            &publisher,
+        );
         repo.create(&book);
 
-        uow.commit().await;
+        uow.commit().await.unwrap();
 
         let rows = sqlx::query(
             "select * from book inner join author_book on author_book.book_id = id where id = $1",
@@ -153,10 +223,19 @@ mod test {
         let repo = DbBookRepository::new(&uow, &publisher);
 
         let book_id = 1;
-        let book = Book::materialize(book_id, "book1-renamed", 10, vec![1], &publisher);
+        let book = Book::materialize(
+            book_id,
+            "book1-renamed",
+            10,
+            vec![1],
+            None,
+            false,
+            0,
+            &publisher,
+        );
         repo.update(&book);
 
-        uow.commit().await;
+        uow.commit().await.unwrap();
 
         let rows = sqlx::query(
             "select * from book inner join author_book on author_book.book_id = id where id = $1",
@@ -179,7 +258,7 @@ mod test {
         let uow = DbUoW::new(pool);
         let repo = DbBookRepository::new(&uow, &publisher);
 
-        let book = repo.by_id(1).await;
+        let book = repo.by_id(1).await.unwrap();
         assert!(book.is_some());
 
         let book = book.unwrap();
@@ -188,4 +267,77 @@ mod test {
         assert_eq!(book.pages_count(), 100);
         assert_eq!(book.authors(), vec![1, 2]);
     }
+
+    #[sqlx::test(fixtures("book"))]
+    async fn archive(pool: PgPool) {
+        let publisher = DomainEventPublisher::new();
+        let uow = DbUoW::new(pool);
+        let repo = DbBookRepository::new(&uow, &publisher);
+
+        assert_eq!(repo.active_by_author(1).await.unwrap().len(), 1);
+
+        let mut book = repo.by_id(1).await.unwrap().unwrap();
+        book.archive();
+        repo.archive(&book);
+        uow.commit().await.unwrap();
+
+        assert!(repo.by_id(1).await.unwrap().unwrap().archived());
+        assert!(repo.active_by_author(1).await.unwrap().is_empty());
+
+        let mut book = repo.by_id(1).await.unwrap().unwrap();
+        book.restore();
+        repo.restore(&book);
+        uow.commit().await.unwrap();
+
+        assert!(!repo.by_id(1).await.unwrap().unwrap().archived());
+    }
+
+    #[sqlx::test(fixtures("book"))]
+    async fn update_conflict(pool: PgPool) {
+        let publisher = DomainEventPublisher::new();
+        let uow1 = DbUoW::new(pool.clone());
+        let repo1 = DbBookRepository::new(&uow1, &publisher);
+        let uow2 = DbUoW::new(pool);
+        let repo2 = DbBookRepository::new(&uow2, &publisher);
+
+        let mut book1 = repo1.by_id(1).await.unwrap().unwrap();
+        let mut book2 = repo2.by_id(1).await.unwrap().unwrap();
+
+        book1.update("book1-first", 100, vec![1, 2]).unwrap();
+        repo1.update(&book1);
+        uow1.commit().await.unwrap();
+
+        book2.update("book1-second", 100, vec![1, 2]).unwrap();
+        repo2.update(&book2);
+        let result = uow2.commit().await;
+
+        assert!(matches!(
+            result,
+            Err(CommitError::Conflict { index: 0, .. })
+        ));
+        assert_eq!(repo1.by_id(1).await.unwrap().unwrap().name(), "book1-first");
+        assert_eq!(repo1.by_id(1).await.unwrap().unwrap().version(), 1);
+    }
+
+    #[sqlx::test(fixtures("book"))]
+    async fn isbn_conflict(pool: PgPool) {
+        let publisher = DomainEventPublisher::new();
+        let uow = DbUoW::new(pool);
+        let repo = DbBookRepository::new(&uow, &publisher);
+        let isbn = Isbn::parse("0-306-40615-2").unwrap();
+
+        let mut book = repo.by_id(1).await.unwrap().unwrap();
+        book.change_isbn(Some(isbn.clone())).unwrap();
+        repo.update(&book);
+        uow.commit().await.unwrap();
+
+        let book = Book::materialize(10, "book10", 100, vec![1], Some(isbn), false, 0, &publisher);
+        repo.create(&book);
+        let result = uow.commit().await;
+
+        assert!(matches!(
+            result,
+            Err(CommitError::Conflict { index: 0, .. })
+        ));
+    }
 }
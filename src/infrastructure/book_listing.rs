@@ -0,0 +1,287 @@
+use crate::application::{
+    book_listing::{BookListItem, BookListing},
+    BoxError,
+};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, PgPool, Row};
+
+pub struct DbBookListing {
+    pool: PgPool,
+}
+
+impl DbBookListing {
+    pub const TABLES: &'static [&'static str] = &["book_listing_author", "book_listing"];
+
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    async fn refresh_book(&self, id: i32) -> Result<(), BoxError> {
+        sqlx::query(
+            "update book_listing set authors = coalesce((\
+                select string_agg(a.full_name, ', ' order by x.ord) \
+                from unnest(book_listing.author_ids) with ordinality as x(author_id, ord) \
+                inner join book_listing_author a on a.id = x.author_id), '') \
+            where id = $1",
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn refresh_author(&self, author_id: i32) -> Result<(), BoxError> {
+        sqlx::query(
+            "update book_listing set authors = coalesce((\
+                select string_agg(a.full_name, ', ' order by x.ord) \
+                from unnest(book_listing.author_ids) with ordinality as x(author_id, ord) \
+                inner join book_listing_author a on a.id = x.author_id), '') \
+            where $1 = any(author_ids)",
+        )
+        .bind(author_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BookListing for DbBookListing {
+    async fn add_author(&self, id: i32, full_name: &str) -> Result<(), BoxError> {
+        sqlx::query(
+            "insert into book_listing_author(id, full_name) values ($1, $2) \
+            on conflict (id) do update set full_name = excluded.full_name",
+        )
+        .bind(id)
+        .bind(full_name)
+        .execute(&self.pool)
+        .await?;
+        self.refresh_author(id).await
+    }
+
+    async fn rename_author(&self, id: i32, full_name: &str) -> Result<(), BoxError> {
+        sqlx::query("update book_listing_author set full_name = $2 where id = $1")
+            .bind(id)
+            .bind(full_name)
+            .execute(&self.pool)
+            .await?;
+        self.refresh_author(id).await
+    }
+
+    async fn add_book(
+        &self,
+        id: i32,
+        name: &str,
+        pages_count: i32,
+        author_ids: &[i32],
+        isbn: Option<&str>,
+    ) -> Result<(), BoxError> {
+        sqlx::query(
+            "insert into book_listing(id, name, pages_count, author_ids, authors, isbn) values ($1, $2, $3, $4, '', $5) \
+            on conflict (id) do update set name = excluded.name, pages_count = excluded.pages_count, author_ids = excluded.author_ids, isbn = excluded.isbn",
+        )
+        .bind(id)
+        .bind(name)
+        .bind(pages_count)
+        .bind(author_ids)
+        .bind(isbn)
+        .execute(&self.pool)
+        .await?;
+        self.refresh_book(id).await
+    }
+
+    async fn rename_book(&self, id: i32, name: &str) -> Result<(), BoxError> {
+        sqlx::query("update book_listing set name = $2 where id = $1")
+            .bind(id)
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn change_pages_count(&self, id: i32, pages_count: i32) -> Result<(), BoxError> {
+        sqlx::query("update book_listing set pages_count = $2 where id = $1")
+            .bind(id)
+            .bind(pages_count)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn change_isbn(&self, id: i32, isbn: Option<&str>) -> Result<(), BoxError> {
+        sqlx::query("update book_listing set isbn = $2 where id = $1")
+            .bind(id)
+            .bind(isbn)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn add_book_author(&self, id: i32, author_id: i32) -> Result<(), BoxError> {
+        sqlx::query(
+            "update book_listing set author_ids = array_append(array_remove(author_ids, $2), $2) where id = $1",
+        )
+        .bind(id)
+        .bind(author_id)
+        .execute(&self.pool)
+        .await?;
+        self.refresh_book(id).await
+    }
+
+    async fn remove_book_author(&self, id: i32, author_id: i32) -> Result<(), BoxError> {
+        sqlx::query(
+            "update book_listing set author_ids = array_remove(author_ids, $2) where id = $1",
+        )
+        .bind(id)
+        .bind(author_id)
+        .execute(&self.pool)
+        .await?;
+        self.refresh_book(id).await
+    }
+
+    async fn archive_book(&self, id: i32, archived: bool) -> Result<(), BoxError> {
+        sqlx::query("update book_listing set archived = $2 where id = $1")
+            .bind(id)
+            .bind(archived)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> Vec<BookListItem> {
+        sqlx::query("select * from book_listing where not archived order by id")
+            .map(|row: PgRow| book_list_item(&row))
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+    }
+
+    async fn by_id(&self, id: i32) -> Option<BookListItem> {
+        sqlx::query("select * from book_listing where id = $1")
+            .bind(id)
+            .map(|row: PgRow| book_list_item(&row))
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap()
+    }
+}
+
+fn book_list_item(row: &PgRow) -> BookListItem {
+    BookListItem {
+        id: row.get("id"),
+        name: row.get("name"),
+        pages_count: row.get("pages_count"),
+        author_ids: row.get("author_ids"),
+        authors: row.get("authors"),
+        isbn: row.get("isbn"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        application::{self, book_listing, book_projector::BookProjector},
+        domain::DomainEventPublisher,
+        infrastructure::{
+            author::DbAuthorRepository, book::DbBookRepository, relay::OutboxRelay, DbEventStore,
+            DbUoW,
+        },
+    };
+
+    #[sqlx::test]
+    async fn project(pool: PgPool) {
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        {
+            let publisher = DomainEventPublisher::new();
+            let authors = DbAuthorRepository::new(&uow, &publisher);
+            application::author::create("f1", "l1", &publisher, &authors, &mut event_store, &uow)
+                .await
+                .unwrap();
+        }
+        {
+            let publisher = DomainEventPublisher::new();
+            let authors = DbAuthorRepository::new(&uow, &publisher);
+            application::author::create("f2", "l2", &publisher, &authors, &mut event_store, &uow)
+                .await
+                .unwrap();
+        }
+        {
+            let publisher = DomainEventPublisher::new();
+            let books = DbBookRepository::new(&uow, &publisher);
+            let authors = DbAuthorRepository::new(&uow, &publisher);
+            application::book::create(
+                "book1",
+                100,
+                vec![2, 1],
+                None,
+                &publisher,
+                &books,
+                &authors,
+                &mut event_store,
+                &uow,
+            )
+            .await
+            .unwrap();
+        }
+        {
+            let publisher = DomainEventPublisher::new();
+            let books = DbBookRepository::new(&uow, &publisher);
+            let authors = DbAuthorRepository::new(&uow, &publisher);
+            application::book::update(
+                1,
+                "book1",
+                100,
+                vec![2, 1],
+                Some(Some("0-306-40615-2")),
+                &publisher,
+                &books,
+                &authors,
+                &mut event_store,
+                &uow,
+            )
+            .await
+            .unwrap();
+        }
+        {
+            let publisher = DomainEventPublisher::new();
+            let authors = DbAuthorRepository::new(&uow, &publisher);
+            application::author::update(
+                1,
+                "f1",
+                "l1-renamed",
+                &publisher,
+                &authors,
+                &mut event_store,
+                &uow,
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut relay = OutboxRelay::new(pool.clone(), 100);
+        relay.register(
+            "book_projector",
+            BookProjector::new(DbBookListing::new(pool.clone())),
+        );
+        relay.dispatch().await.unwrap();
+
+        let listing = DbBookListing::new(pool);
+        assert_eq!(
+            book_listing::list(&listing).await,
+            vec![BookListItem {
+                id: 1,
+                name: String::from("book1"),
+                pages_count: 100,
+                author_ids: vec![2, 1],
+                authors: String::from("f2 l2, f1 l1-renamed"),
+                isbn: Some(String::from("9780306406157")),
+            }]
+        );
+        assert!(matches!(
+            book_listing::get(2, &listing).await,
+            Err(application::ApplicationError::NotFound(2))
+        ));
+    }
+}
@@ -0,0 +1,268 @@
+use crate::application::catalog::{
+    AuthorFilter, AuthorSummary, BookFilter, BookSummary, Catalog, Cursor, Sort,
+};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, PgPool, Postgres, QueryBuilder, Row};
+
+pub struct DbCatalog {
+    pool: PgPool,
+}
+
+impl DbCatalog {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl Catalog for DbCatalog {
+    async fn book(&self, id: i32) -> Option<BookSummary> {
+        let mut query = QueryBuilder::new(BOOK_SUMMARY);
+        query.push(" and id = ").push_bind(id);
+
+        query
+            .build()
+            .map(|row: PgRow| book_summary(&row))
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap()
+    }
+
+    async fn books(
+        &self,
+        filter: &BookFilter,
+        sort: Sort,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> Vec<BookSummary> {
+        let mut query = QueryBuilder::new(BOOK_SUMMARY);
+        filter_books(&mut query, filter);
+        keyset(&mut query, "name", sort, after, limit);
+
+        query
+            .build()
+            .map(|row: PgRow| book_summary(&row))
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+    }
+
+    async fn authors(
+        &self,
+        filter: &AuthorFilter,
+        sort: Sort,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> Vec<AuthorSummary> {
+        let mut query = QueryBuilder::new(
+            "select id, first_name, last_name, full_name, archived from author where true",
+        );
+        if !filter.include_archived {
+            query.push(" and not archived");
+        }
+        if let Some(name) = &filter.name {
+            query
+                .push(" and strpos(lower(full_name), lower(")
+                .push_bind(name.clone())
+                .push(")) > 0");
+        }
+        keyset(&mut query, "full_name", sort, after, limit);
+
+        query
+            .build()
+            .map(|row: PgRow| AuthorSummary {
+                id: row.get("id"),
+                first_name: row.get("first_name"),
+                last_name: row.get("last_name"),
+                full_name: row.get("full_name"),
+                archived: row.get("archived"),
+            })
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+    }
+}
+
+const BOOK_SUMMARY: &str = "select id, name, pages_count, isbn, archived, \
+    array(select author_id from author_book where book_id = book.id order by author_id) as authors \
+    from book where true";
+
+fn book_summary(row: &PgRow) -> BookSummary {
+    BookSummary {
+        id: row.get("id"),
+        name: row.get("name"),
+        pages_count: row.get("pages_count"),
+        authors: row.get("authors"),
+        isbn: row.get("isbn"),
+        archived: row.get("archived"),
+    }
+}
+
+// Expects the query to select from `book` and to end in a `where` clause.
+pub(super) fn filter_books(query: &mut QueryBuilder<Postgres>, filter: &BookFilter) {
+    if !filter.include_archived {
+        query.push(" and not archived");
+    }
+    if let Some(author_id) = filter.author_id {
+        query
+            .push(" and exists (select 1 from author_book where book_id = book.id and author_id = ")
+            .push_bind(author_id)
+            .push(")");
+    }
+    if let Some(name) = &filter.name {
+        query
+            .push(" and strpos(lower(name), lower(")
+            .push_bind(name.clone())
+            .push(")) > 0");
+    }
+    if let Some(min) = filter.min_pages_count {
+        query.push(" and pages_count >= ").push_bind(min);
+    }
+    if let Some(max) = filter.max_pages_count {
+        query.push(" and pages_count <= ").push_bind(max);
+    }
+}
+
+fn keyset(
+    query: &mut QueryBuilder<Postgres>,
+    name: &'static str,
+    sort: Sort,
+    after: Option<&Cursor>,
+    limit: i64,
+) {
+    match after {
+        Some(Cursor::Id(id)) => {
+            query.push(" and id > ").push_bind(*id);
+        }
+        Some(Cursor::Name(after_name, id)) => {
+            query
+                .push(format!(" and ({}, id) > (", name))
+                .push_bind(after_name.clone())
+                .push(", ")
+                .push_bind(*id)
+                .push(")");
+        }
+        None => {}
+    }
+    match sort {
+        Sort::Id => query.push(" order by id"),
+        Sort::Name => query.push(format!(" order by {}, id", name)),
+    };
+    query.push(" limit ").push_bind(limit);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::application::{catalog, ApplicationError};
+    use sqlx::Executor;
+
+    async fn seed(pool: &PgPool) {
+        pool.execute(
+            "insert into author(id, first_name, last_name, full_name) values \
+            (1, 'Terry', 'Pratchett', 'Terry Pratchett'), (2, 'Neil', 'Gaiman', 'Neil Gaiman'); \
+            insert into book(id, name, pages_count) values \
+            (1, 'Mort', 243), (2, 'Good Omens', 412), (3, 'Sourcery', 243), (4, 'Coraline', 162), (5, 'Mort', 300); \
+            insert into author_book(author_id, book_id) values (1, 1), (1, 2), (2, 2), (1, 3), (2, 4), (1, 5); \
+            update book set archived = true where id = 3;",
+        )
+        .await
+        .unwrap();
+    }
+
+    #[sqlx::test]
+    async fn books_filtered(pool: PgPool) {
+        seed(&pool).await;
+        let catalog = DbCatalog::new(pool);
+
+        let filter = BookFilter {
+            author_id: Some(1),
+            min_pages_count: Some(250),
+            ..BookFilter::default()
+        };
+        let page = catalog::books(&filter, Sort::Id, None, 10, &catalog)
+            .await
+            .unwrap();
+        let ids: Vec<i32> = page.items.iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![2, 5]);
+        assert_eq!(page.items[0].authors, vec![1, 2]);
+        assert_eq!(page.next, None);
+
+        let filter = BookFilter {
+            name: Some(String::from("SOUR")),
+            include_archived: true,
+            ..BookFilter::default()
+        };
+        let page = catalog::books(&filter, Sort::Id, None, 10, &catalog)
+            .await
+            .unwrap();
+        let ids: Vec<i32> = page.items.iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![3]);
+    }
+
+    #[sqlx::test]
+    async fn books_paginated_by_name(pool: PgPool) {
+        seed(&pool).await;
+        let catalog = DbCatalog::new(pool);
+        let filter = BookFilter::default();
+
+        let mut names = Vec::new();
+        let mut after = None;
+        loop {
+            let page = catalog::books(&filter, Sort::Name, after.as_deref(), 2, &catalog)
+                .await
+                .unwrap();
+            names.extend(page.items.into_iter().map(|b| (b.name, b.id)));
+            match page.next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+
+        assert_eq!(
+            names,
+            vec![
+                (String::from("Coraline"), 4),
+                (String::from("Good Omens"), 2),
+                (String::from("Mort"), 1),
+                (String::from("Mort"), 5),
+            ]
+        );
+
+        let wrong_sort = Cursor::Id(1).encode();
+        let result = catalog::books(&filter, Sort::Name, Some(&wrong_sort), 2, &catalog).await;
+        assert!(matches!(result, Err(ApplicationError::InvalidQuery(_))));
+    }
+
+    #[sqlx::test]
+    async fn authors_by_name(pool: PgPool) {
+        seed(&pool).await;
+        let catalog = DbCatalog::new(pool);
+
+        let page = catalog::authors(&AuthorFilter::default(), Sort::Name, None, 1, &catalog)
+            .await
+            .unwrap();
+        assert_eq!(page.items[0].full_name, "Neil Gaiman");
+
+        let page = catalog::authors(
+            &AuthorFilter::default(),
+            Sort::Name,
+            page.next.as_deref(),
+            1,
+            &catalog,
+        )
+        .await
+        .unwrap();
+        assert_eq!(page.items[0].full_name, "Terry Pratchett");
+        assert_eq!(page.next, None);
+
+        let filter = AuthorFilter {
+            name: Some(String::from("gai")),
+            ..AuthorFilter::default()
+        };
+        let page = catalog::authors(&filter, Sort::Id, None, 10, &catalog)
+            .await
+            .unwrap();
+        assert_eq!(page.items.len(), 1);
+    }
+}
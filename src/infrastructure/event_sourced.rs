@@ -0,0 +1,328 @@
+use super::{author::DbAuthorRepository, book::DbBookRepository, DbEventStore, DbUoW, Statement};
+use crate::{
+    application::{BoxError, EventStore},
+    domain::{
+        author::{Author, AuthorRepository},
+        book::{Book, BookRepository},
+        isbn::Isbn,
+        Aggregate, DomainEvent, DomainEventPublisher,
+    },
+};
+use async_trait::async_trait;
+use std::{error::Error, fmt};
+
+pub struct EventSourcedBookRepository<'a, 'b, 'c> {
+    state: DbBookRepository<'a, 'b, 'c>,
+    events: DbEventStore<'a>,
+    publisher: &'b DomainEventPublisher<'c>,
+}
+
+impl<'a, 'b, 'c> EventSourcedBookRepository<'a, 'b, 'c> {
+    pub fn new(db: &'a DbUoW, publisher: &'b DomainEventPublisher<'c>) -> Self {
+        Self {
+            state: DbBookRepository::new(db, publisher),
+            events: DbEventStore::new(db),
+            publisher,
+        }
+    }
+}
+
+#[async_trait]
+impl<'a, 'b, 'c> BookRepository<'b, 'c> for EventSourcedBookRepository<'a, 'b, 'c> {
+    fn create(&self, book: &Book) {
+        self.state.create(book);
+    }
+
+    fn update(&self, book: &Book) {
+        self.state.update(book);
+    }
+
+    fn archive(&self, book: &Book) {
+        self.state.archive(book);
+    }
+
+    fn restore(&self, book: &Book) {
+        self.state.restore(book);
+    }
+
+    async fn next_identity(&self) -> i32 {
+        self.state.next_identity().await
+    }
+
+    async fn by_id(&self, id: i32) -> Result<Option<Book<'b, 'c>>, BoxError> {
+        let history = history(&self.events, Aggregate::Book, id).await?;
+        Ok(Book::from_history(history, self.publisher))
+    }
+
+    async fn id_by_isbn(&self, isbn: &Isbn) -> Option<i32> {
+        self.state.id_by_isbn(isbn).await
+    }
+
+    async fn active_by_author(&self, author_id: i32) -> Result<Vec<Book<'b, 'c>>, BoxError> {
+        let mut books = Vec::new();
+        for book in self.state.active_by_author(author_id).await? {
+            books.extend(self.by_id(book.id()).await?);
+        }
+        Ok(books)
+    }
+}
+
+pub struct EventSourcedAuthorRepository<'a, 'b, 'c> {
+    state: DbAuthorRepository<'a, 'b, 'c>,
+    events: DbEventStore<'a>,
+    publisher: &'b DomainEventPublisher<'c>,
+}
+
+impl<'a, 'b, 'c> EventSourcedAuthorRepository<'a, 'b, 'c> {
+    pub fn new(db: &'a DbUoW, publisher: &'b DomainEventPublisher<'c>) -> Self {
+        Self {
+            state: DbAuthorRepository::new(db, publisher),
+            events: DbEventStore::new(db),
+            publisher,
+        }
+    }
+}
+
+#[async_trait]
+impl<'a, 'b, 'c> AuthorRepository<'b, 'c> for EventSourcedAuthorRepository<'a, 'b, 'c> {
+    fn create(&self, author: &Author) {
+        self.state.create(author);
+    }
+
+    fn update(&self, author: &Author) {
+        self.state.update(author);
+    }
+
+    fn archive(&self, author: &Author) {
+        self.state.archive(author);
+    }
+
+    fn restore(&self, author: &Author) {
+        self.state.restore(author);
+    }
+
+    async fn next_identity(&self) -> i32 {
+        self.state.next_identity().await
+    }
+
+    async fn by_id(&self, id: i32) -> Result<Option<Author<'b, 'c>>, BoxError> {
+        let history = history(&self.events, Aggregate::Author, id).await?;
+        Ok(Author::from_history(history, self.publisher))
+    }
+}
+
+#[derive(Debug)]
+pub enum RebuildError {
+    Load(BoxError),
+    Untracked { aggregate: Aggregate, ids: Vec<i32> },
+}
+
+impl fmt::Display for RebuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RebuildError::Load(e) => write!(f, "failed to load event streams: {}", e),
+            RebuildError::Untracked { aggregate, ids } => write!(
+                f,
+                "{} rows without an event stream: {:?}",
+                aggregate.name(),
+                ids
+            ),
+        }
+    }
+}
+
+impl Error for RebuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RebuildError::Load(e) => Some(e.as_ref()),
+            RebuildError::Untracked { .. } => None,
+        }
+    }
+}
+
+impl From<BoxError> for RebuildError {
+    fn from(e: BoxError) -> Self {
+        RebuildError::Load(e)
+    }
+}
+
+impl From<sqlx::Error> for RebuildError {
+    fn from(e: sqlx::Error) -> Self {
+        RebuildError::Load(e.into())
+    }
+}
+
+// Only rows that have a stream are replaced. Rows written without one would be lost, so
+// nothing is staged while any exist.
+pub async fn rebuild_state(db: &DbUoW) -> Result<(), RebuildError> {
+    for aggregate in [Aggregate::Author, Aggregate::Book] {
+        let ids = untracked_ids(db, aggregate).await?;
+        if !ids.is_empty() {
+            return Err(RebuildError::Untracked { aggregate, ids });
+        }
+    }
+
+    let publisher = DomainEventPublisher::new();
+    let books = EventSourcedBookRepository::new(db, &publisher);
+    let authors = EventSourcedAuthorRepository::new(db, &publisher);
+    let author_ids = stream_ids(db, Aggregate::Author).await?;
+    let book_ids = stream_ids(db, Aggregate::Book).await?;
+
+    let mut rebuilt_authors = Vec::with_capacity(author_ids.len());
+    for id in &author_ids {
+        let author = authors.by_id(*id).await?;
+        rebuilt_authors.push(author.ok_or_else(|| unreplayable(Aggregate::Author, *id))?);
+    }
+    let mut rebuilt_books = Vec::with_capacity(book_ids.len());
+    for id in &book_ids {
+        let book = books.by_id(*id).await?;
+        rebuilt_books.push(book.ok_or_else(|| unreplayable(Aggregate::Book, *id))?);
+    }
+
+    db.add(
+        Statement::new("delete from author_book where book_id = any($1)").bind(book_ids.clone()),
+    );
+    db.add(Statement::new("delete from book where id = any($1)").bind(book_ids));
+    db.add(Statement::new("delete from author where id = any($1)").bind(author_ids));
+
+    for author in &rebuilt_authors {
+        authors.create(author);
+        if author.archived() {
+            authors.archive(author);
+        }
+    }
+    for book in &rebuilt_books {
+        books.create(book);
+        if book.archived() {
+            books.archive(book);
+        }
+    }
+    Ok(())
+}
+
+fn unreplayable(aggregate: Aggregate, id: i32) -> RebuildError {
+    RebuildError::Load(
+        format!(
+            "{} stream {} does not start with its creation",
+            aggregate.name(),
+            id
+        )
+        .into(),
+    )
+}
+
+async fn untracked_ids(db: &DbUoW, aggregate: Aggregate) -> Result<Vec<i32>, sqlx::Error> {
+    let sql = match aggregate {
+        Aggregate::Author => "select id from author where id not in (select aggregate_id from stored_event where aggregate_type = $1) order by id",
+        Aggregate::Book => "select id from book where id not in (select aggregate_id from stored_event where aggregate_type = $1) order by id",
+    };
+    let ids: Vec<(i32,)> = sqlx::query_as(sql)
+        .bind(aggregate.name())
+        .fetch_all(&db.pool)
+        .await?;
+    Ok(ids.into_iter().map(|(id,)| id).collect())
+}
+
+async fn stream_ids(db: &DbUoW, aggregate: Aggregate) -> Result<Vec<i32>, sqlx::Error> {
+    let ids: Vec<(i32,)> = sqlx::query_as(
+        "select distinct aggregate_id from stored_event where aggregate_type = $1 order by aggregate_id",
+    )
+    .bind(aggregate.name())
+    .fetch_all(&db.pool)
+    .await?;
+    Ok(ids.into_iter().map(|(id,)| id).collect())
+}
+
+async fn history(
+    events: &DbEventStore<'_>,
+    aggregate: Aggregate,
+    id: i32,
+) -> Result<Vec<DomainEvent>, BoxError> {
+    let mut history = Vec::new();
+    for stored_event in events.load_stream(aggregate, id).await? {
+        history.push(stored_event.domain_event()?);
+    }
+    Ok(history)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::application::{self, UoW};
+    use sqlx::{PgPool, Row};
+
+    #[sqlx::test]
+    async fn by_id(pool: PgPool) {
+        let uow = DbUoW::new(pool);
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let authors = EventSourcedAuthorRepository::new(&uow, &publisher);
+        let books = EventSourcedBookRepository::new(&uow, &publisher);
+
+        {
+            let publisher = DomainEventPublisher::new();
+            publisher.subscribe(|e| event_store.append(e));
+
+            Author::new(1, "f1", "l1", &publisher).unwrap();
+            let mut book = Book::new(1, "book1", 100, vec![1], None, &publisher).unwrap();
+            book.update("book1-renamed", 100, vec![1]).unwrap();
+        }
+        uow.commit().await.unwrap();
+
+        let author = authors.by_id(1).await.unwrap().unwrap();
+        assert_eq!(author.full_name(), "f1 l1");
+
+        let book = books.by_id(1).await.unwrap().unwrap();
+        assert_eq!(book.name(), "book1-renamed");
+        assert_eq!(book.authors(), vec![1]);
+
+        assert!(books.by_id(2).await.unwrap().is_none());
+    }
+
+    #[sqlx::test]
+    async fn rebuild(pool: PgPool) {
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+
+        {
+            let publisher = DomainEventPublisher::new();
+            let authors = EventSourcedAuthorRepository::new(&uow, &publisher);
+            application::author::create("f1", "l1", &publisher, &authors, &mut event_store, &uow)
+                .await
+                .unwrap();
+        }
+
+        sqlx::query("update author set full_name = 'corrupted'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        rebuild_state(&uow).await.unwrap();
+        uow.commit().await.unwrap();
+
+        let rows = sqlx::query("select * from author")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get::<&str, _>("full_name"), "f1 l1");
+    }
+
+    #[sqlx::test(fixtures(path = "fixtures", scripts("author")))]
+    async fn rebuild_refuses_rows_without_stream(pool: PgPool) {
+        let uow = DbUoW::new(pool.clone());
+
+        let result = rebuild_state(&uow).await;
+
+        assert!(matches!(
+            result,
+            Err(RebuildError::Untracked { aggregate: Aggregate::Author, ids }) if ids == vec![1, 2]
+        ));
+        uow.commit().await.unwrap();
+        let rows = sqlx::query("select * from author")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+    }
+}
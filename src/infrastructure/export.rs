@@ -0,0 +1,276 @@
+use super::{catalog::filter_books, import::join_list};
+use crate::application::catalog::BookFilter;
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgRow, PgPool, Postgres, QueryBuilder, Row};
+use std::{error::Error, fmt, io::Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Ndjson,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedAuthor {
+    pub id: i32,
+    pub first_name: String,
+    pub last_name: String,
+    pub full_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExportedBook {
+    pub id: i32,
+    pub name: String,
+    pub pages_count: i32,
+    pub isbn: Option<String>,
+    pub archived: bool,
+    pub authors: Vec<ExportedAuthor>,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(std::io::Error),
+    Csv(csv::Error),
+    Json(serde_json::Error),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Io(e) => write!(f, "export write error: {}", e),
+            ExportError::Csv(e) => write!(f, "export write error: {}", e),
+            ExportError::Json(e) => write!(f, "export write error: {}", e),
+            ExportError::Database(e) => write!(f, "export database error: {}", e),
+        }
+    }
+}
+
+impl Error for ExportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ExportError::Io(e) => Some(e),
+            ExportError::Csv(e) => Some(e),
+            ExportError::Json(e) => Some(e),
+            ExportError::Database(e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+impl From<csv::Error> for ExportError {
+    fn from(e: csv::Error) -> Self {
+        ExportError::Csv(e)
+    }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(e: serde_json::Error) -> Self {
+        ExportError::Json(e)
+    }
+}
+
+impl From<sqlx::Error> for ExportError {
+    fn from(e: sqlx::Error) -> Self {
+        ExportError::Database(e)
+    }
+}
+
+// Books are written as they arrive from the database, so memory use does not grow with the
+// catalogue. CSV has one row per book, with author ids and full names joined by `;`.
+pub async fn export_books(
+    pool: &PgPool,
+    filter: &BookFilter,
+    format: ExportFormat,
+    writer: impl Write,
+) -> Result<usize, ExportError> {
+    let mut query: QueryBuilder<Postgres> = QueryBuilder::new(
+        "select id, name, pages_count, isbn, archived, coalesce((\
+            select json_agg(json_build_object(\
+                'id', a.id, 'first_name', a.first_name, 'last_name', a.last_name, 'full_name', a.full_name\
+            ) order by a.id) \
+            from author_book ab inner join author a on a.id = ab.author_id \
+            where ab.book_id = book.id), '[]')::text as authors \
+        from book where true",
+    );
+    filter_books(&mut query, filter);
+    query.push(" order by id");
+
+    let mut sink = Sink::new(format, writer)?;
+    let mut rows = query.build().fetch(pool);
+    let mut count = 0;
+    while let Some(row) = rows.try_next().await? {
+        sink.write(&book(&row)?)?;
+        count += 1;
+    }
+    sink.finish()?;
+
+    Ok(count)
+}
+
+fn book(row: &PgRow) -> Result<ExportedBook, ExportError> {
+    Ok(ExportedBook {
+        id: row.get("id"),
+        name: row.get("name"),
+        pages_count: row.get("pages_count"),
+        isbn: row.get("isbn"),
+        archived: row.get("archived"),
+        authors: serde_json::from_str(row.get("authors"))?,
+    })
+}
+
+enum Sink<W: Write> {
+    Json { writer: W, first: bool },
+    Ndjson(W),
+    Csv(Box<csv::Writer<W>>),
+}
+
+impl<W: Write> Sink<W> {
+    fn new(format: ExportFormat, mut writer: W) -> Result<Self, ExportError> {
+        Ok(match format {
+            ExportFormat::Json => {
+                writer.write_all(b"[")?;
+                Sink::Json {
+                    writer,
+                    first: true,
+                }
+            }
+            ExportFormat::Ndjson => Sink::Ndjson(writer),
+            // Authors are lists in one field each, written the way import reads them (see
+            // `import::split_list`).
+            ExportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+                writer.write_record([
+                    "id",
+                    "name",
+                    "pages_count",
+                    "isbn",
+                    "archived",
+                    "author_ids",
+                    "authors",
+                ])?;
+                Sink::Csv(Box::new(writer))
+            }
+        })
+    }
+
+    fn write(&mut self, book: &ExportedBook) -> Result<(), ExportError> {
+        match self {
+            Sink::Json { writer, first } => {
+                writer.write_all(if *first { b"\n" } else { b",\n" })?;
+                *first = false;
+                serde_json::to_writer(writer, book)?;
+            }
+            Sink::Ndjson(writer) => {
+                serde_json::to_writer(&mut *writer, book)?;
+                writer.write_all(b"\n")?;
+            }
+            Sink::Csv(writer) => {
+                let ids: Vec<String> = book.authors.iter().map(|a| a.id.to_string()).collect();
+                let names = join_list(book.authors.iter().map(|a| a.full_name.as_str()));
+                writer.write_record([
+                    book.id.to_string(),
+                    book.name.clone(),
+                    book.pages_count.to_string(),
+                    book.isbn.clone().unwrap_or_default(),
+                    book.archived.to_string(),
+                    ids.join(";"),
+                    names,
+                ])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), ExportError> {
+        match self {
+            Sink::Json { mut writer, first } => {
+                writer.write_all(if first { b"]\n" } else { b"\n]\n" })?;
+                writer.flush()?;
+            }
+            Sink::Ndjson(mut writer) => writer.flush()?,
+            Sink::Csv(mut writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sqlx::Executor;
+
+    async fn seed(pool: &PgPool) {
+        pool.execute(
+            "insert into author(id, first_name, last_name, full_name) values \
+                (1, 'Terry', 'Pratchett', 'Terry Pratchett'), (2, 'Neil', 'Gaiman', 'Neil Gaiman'); \
+            insert into book(id, name, pages_count, archived) values \
+                (1, 'Good Omens', 412, false), (2, 'Mort, a novel', 243, false), (3, 'Lost', 10, true); \
+            update book set isbn = '9780552131063' where id = 2; \
+            insert into author_book(author_id, book_id) values (2, 1), (1, 1), (1, 2), (1, 3);",
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn export(pool: &PgPool, filter: &BookFilter, format: ExportFormat) -> String {
+        let mut out = Vec::new();
+        export_books(pool, filter, format, &mut out).await.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn formats(pool: PgPool) {
+        seed(&pool).await;
+        let filter = BookFilter::default();
+
+        let csv = export(&pool, &filter, ExportFormat::Csv).await;
+        assert_eq!(
+            csv,
+            "id,name,pages_count,isbn,archived,author_ids,authors\n\
+            1,Good Omens,412,,false,1;2,Terry Pratchett;Neil Gaiman\n\
+            2,\"Mort, a novel\",243,9780552131063,false,1,Terry Pratchett\n"
+        );
+
+        let ndjson = export(&pool, &filter, ExportFormat::Ndjson).await;
+        let lines: Vec<serde_json::Value> = ndjson
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["authors"][0]["full_name"], "Terry Pratchett");
+
+        let json: serde_json::Value =
+            serde_json::from_str(&export(&pool, &filter, ExportFormat::Json).await).unwrap();
+        assert_eq!(json, serde_json::Value::Array(lines));
+    }
+
+    #[sqlx::test]
+    async fn filtered(pool: PgPool) {
+        seed(&pool).await;
+
+        let filter = BookFilter {
+            include_archived: true,
+            max_pages_count: Some(300),
+            ..Default::default()
+        };
+        let json: Vec<serde_json::Value> =
+            serde_json::from_str(&export(&pool, &filter, ExportFormat::Json).await).unwrap();
+        let ids: Vec<&serde_json::Value> = json.iter().map(|b| &b["id"]).collect();
+        assert_eq!(ids, [2, 3]);
+
+        let filter = BookFilter {
+            name: Some(String::from("nothing")),
+            ..Default::default()
+        };
+        assert_eq!(export(&pool, &filter, ExportFormat::Json).await, "[]\n");
+    }
+}
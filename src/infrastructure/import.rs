@@ -0,0 +1,713 @@
+use super::{author::DbAuthorRepository, book::DbBookRepository, DbEventStore, DbUoW, Statement};
+use crate::{
+    application::{self, ApplicationError, CommitError, UoW},
+    domain::{
+        author::Author, book::Book, isbn::Isbn, DomainError, DomainEvent, DomainEventPublisher,
+    },
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    io::Read,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    Author,
+    Book,
+}
+
+impl RecordKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecordKind::Author => "author",
+            RecordKind::Book => "book",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+enum AuthorRefs {
+    List(Vec<String>),
+    Joined(String),
+}
+
+impl Default for AuthorRefs {
+    fn default() -> Self {
+        AuthorRefs::List(Vec::new())
+    }
+}
+
+// CSV files carry the author references of a book in one column as a list (see
+// `split_list`), JSON files as an array. A reference is an external key from an earlier
+// author row or a full name.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ImportRecord {
+    pub kind: RecordKind,
+    #[serde(default)]
+    pub key: Option<String>,
+    #[serde(default)]
+    pub first_name: Option<String>,
+    #[serde(default)]
+    pub last_name: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub pages_count: Option<i32>,
+    #[serde(default)]
+    pub isbn: Option<String>,
+    #[serde(default)]
+    authors: AuthorRefs,
+}
+
+impl ImportRecord {
+    pub fn author_refs(&self) -> Result<Vec<String>, String> {
+        let refs = match &self.authors {
+            AuthorRefs::List(refs) => refs.iter().map(|r| String::from(r.trim())).collect(),
+            AuthorRefs::Joined(refs) => split_list(refs)?,
+        };
+        Ok(refs.into_iter().filter(|r| !r.is_empty()).collect())
+    }
+}
+
+// A list in a single CSV field: items are separated by `;` and trimmed. An item that
+// contains `;` or `"`, or starts or ends with whitespace, is put in double quotes, with
+// inner quotes doubled, as CSV does for fields: `tp; "Smith; Jones"; "The ""Doc"""`.
+pub(crate) fn join_list<'a>(items: impl IntoIterator<Item = &'a str>) -> String {
+    let items: Vec<String> = items
+        .into_iter()
+        .map(|item| {
+            if item.contains([';', '"']) || item.trim() != item {
+                format!("\"{}\"", item.replace('"', "\"\""))
+            } else {
+                String::from(item)
+            }
+        })
+        .collect();
+    items.join(";")
+}
+
+pub(crate) fn split_list(list: &str) -> Result<Vec<String>, String> {
+    let mut items = Vec::new();
+    let mut rest = list;
+    loop {
+        rest = rest.trim_start();
+        let item = if let Some(quoted) = rest.strip_prefix('"') {
+            let mut item = String::new();
+            let mut chars = quoted.char_indices();
+            loop {
+                match chars.next() {
+                    Some((i, '"')) if quoted[i + 1..].starts_with('"') => {
+                        item.push('"');
+                        chars.next();
+                    }
+                    Some((i, '"')) => {
+                        rest = quoted[i + 1..].trim_start();
+                        break;
+                    }
+                    Some((_, c)) => item.push(c),
+                    None => return Err(format!("unterminated quote in `{}`", list)),
+                }
+            }
+            if !rest.is_empty() && !rest.starts_with(';') {
+                return Err(format!("`;` expected after quoted item in `{}`", list));
+            }
+            item
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            let item = String::from(rest[..end].trim());
+            rest = &rest[end..];
+            item
+        };
+        items.push(item);
+        match rest.strip_prefix(';') {
+            Some(next) => rest = next,
+            None => return Ok(items),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum RowStatus {
+    Created { id: i32 },
+    Valid,
+    Skipped { id: i32 },
+    Failed { errors: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RowReport {
+    pub row: usize,
+    pub kind: RecordKind,
+    pub key: Option<String>,
+    #[serde(flatten)]
+    pub status: RowStatus,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub resumed_at: usize,
+    pub rows: Vec<RowReport>,
+}
+
+impl ImportReport {
+    pub fn failed(&self) -> usize {
+        self.rows
+            .iter()
+            .filter(|r| matches!(r.status, RowStatus::Failed { .. }))
+            .count()
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Csv(csv::Error),
+    Json(serde_json::Error),
+    Database(sqlx::Error),
+    Commit(CommitError),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Csv(e) => write!(f, "invalid csv: {}", e),
+            ImportError::Json(e) => write!(f, "invalid json: {}", e),
+            ImportError::Database(e) => write!(f, "import database error: {}", e),
+            ImportError::Commit(e) => write!(f, "import database error: {}", e),
+        }
+    }
+}
+
+impl Error for ImportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImportError::Csv(e) => Some(e),
+            ImportError::Json(e) => Some(e),
+            ImportError::Database(e) => Some(e),
+            ImportError::Commit(e) => Some(e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ImportError {
+    fn from(e: sqlx::Error) -> Self {
+        ImportError::Database(e)
+    }
+}
+
+impl From<CommitError> for ImportError {
+    fn from(e: CommitError) -> Self {
+        ImportError::Commit(e)
+    }
+}
+
+pub fn read_csv(reader: impl Read) -> Result<Vec<ImportRecord>, ImportError> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .map_err(ImportError::Csv)
+}
+
+pub fn read_json(reader: impl Read) -> Result<Vec<ImportRecord>, ImportError> {
+    serde_json::from_reader(reader).map_err(ImportError::Json)
+}
+
+pub struct Importer {
+    pool: PgPool,
+    dry_run: bool,
+}
+
+impl Importer {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            dry_run: false,
+        }
+    }
+
+    // Rows are validated and their references resolved, but nothing is written, not even the
+    // job position.
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    // Every row is committed together with its import_key and the job position, so a run
+    // that stops resumes right after the last row it stored. Failed rows are recorded with
+    // the job and tried again by the next run, even though the position moved past them.
+    // Rows with a key already imported are skipped.
+    pub async fn import(
+        &self,
+        job: &str,
+        records: &[ImportRecord],
+    ) -> Result<ImportReport, ImportError> {
+        let (resumed_at, retry) = if self.dry_run {
+            (0, HashSet::new())
+        } else {
+            (self.position(job).await?, self.failed_rows(job).await?)
+        };
+        let mut report = ImportReport {
+            resumed_at,
+            rows: Vec::new(),
+        };
+        let mut planned = Planned::default();
+
+        for (index, record) in records.iter().enumerate() {
+            let row = index + 1;
+            if index < resumed_at && !retry.contains(&row) {
+                continue;
+            }
+            let status = match self.imported(record).await? {
+                Some(id) => {
+                    if !self.dry_run {
+                        self.save_row(job, row, None).await?;
+                    }
+                    RowStatus::Skipped { id }
+                }
+                None if self.dry_run => self.validate(record, &mut planned).await?,
+                None => self.create(job, row, record).await?,
+            };
+            report.rows.push(RowReport {
+                row,
+                kind: record.kind,
+                key: record.key.clone(),
+                status,
+            });
+        }
+
+        Ok(report)
+    }
+
+    async fn create(
+        &self,
+        job: &str,
+        row: usize,
+        record: &ImportRecord,
+    ) -> Result<RowStatus, ImportError> {
+        let uow = DbUoW::new(self.pool.clone());
+        for statement in progress(job, row) {
+            uow.add(statement);
+        }
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        if let Some(key) = &record.key {
+            let (uow, kind, key) = (&uow, record.kind.name(), key.clone());
+            publisher.subscribe(move |e| {
+                if let DomainEvent::AuthorCreated(_) | DomainEvent::BookCreated(_) = e {
+                    uow.add(
+                        Statement::new("insert into import_key(kind, key, id) values ($1, $2, $3)")
+                            .bind(kind)
+                            .bind(key.clone())
+                            .bind(e.stream().1),
+                    );
+                }
+            });
+        }
+        let authors = DbAuthorRepository::new(&uow, &publisher);
+
+        let result = match record.kind {
+            RecordKind::Author => {
+                application::author::create(
+                    record.first_name.as_deref().unwrap_or_default(),
+                    record.last_name.as_deref().unwrap_or_default(),
+                    &publisher,
+                    &authors,
+                    &mut event_store,
+                    &uow,
+                )
+                .await
+            }
+            RecordKind::Book => match self.resolve(record, &Planned::default()).await? {
+                Ok(author_ids) => {
+                    let books = DbBookRepository::new(&uow, &publisher);
+                    application::book::create(
+                        record.name.as_deref().unwrap_or_default(),
+                        record.pages_count.unwrap_or_default(),
+                        author_ids,
+                        record.isbn.as_deref(),
+                        &publisher,
+                        &books,
+                        &authors,
+                        &mut event_store,
+                        &uow,
+                    )
+                    .await
+                }
+                Err(errors) => {
+                    self.save_row(job, row, Some(&errors)).await?;
+                    return Ok(RowStatus::Failed { errors });
+                }
+            },
+        };
+
+        let errors = match result {
+            Ok(id) => return Ok(RowStatus::Created { id }),
+            Err(ApplicationError::Domain(errors)) => errors.iter().map(|e| e.to_string()).collect(),
+            Err(e) => vec![e.to_string()],
+        };
+        self.save_row(job, row, Some(&errors)).await?;
+        Ok(RowStatus::Failed { errors })
+    }
+
+    // Mirrors `create` without writing: the domain rules run on a throwaway publisher and
+    // authors defined earlier in the file count as existing.
+    async fn validate(
+        &self,
+        record: &ImportRecord,
+        planned: &mut Planned,
+    ) -> Result<RowStatus, ImportError> {
+        let publisher = DomainEventPublisher::new();
+        let mut errors = Vec::new();
+
+        match record.kind {
+            RecordKind::Author => {
+                let first_name = record.first_name.as_deref().unwrap_or_default();
+                let last_name = record.last_name.as_deref().unwrap_or_default();
+                match Author::new(0, first_name, last_name, &publisher) {
+                    Ok(author) => planned.add(record.key.as_deref(), author.full_name()),
+                    Err(e) => errors.extend(e.iter().map(|e| e.to_string())),
+                }
+            }
+            RecordKind::Book => {
+                let author_ids = match self.resolve(record, planned).await? {
+                    Ok(ids) => ids,
+                    Err(e) => {
+                        errors.extend(e);
+                        vec![0]
+                    }
+                };
+                let isbn = match record.isbn.as_deref().map(Isbn::parse).transpose() {
+                    Ok(isbn) => isbn,
+                    Err(e) => {
+                        errors.push(e.to_string());
+                        None
+                    }
+                };
+                if let Some(isbn) = &isbn {
+                    let used: Option<(i32,)> =
+                        sqlx::query_as("select id from book where isbn = $1")
+                            .bind(isbn.as_str())
+                            .fetch_optional(&self.pool)
+                            .await?;
+                    if used.is_some() || !planned.isbns.insert(isbn.clone()) {
+                        errors.push(DomainError::DuplicateIsbn(isbn.hyphenated()).to_string());
+                    }
+                }
+                let name = record.name.as_deref().unwrap_or_default();
+                let pages_count = record.pages_count.unwrap_or_default();
+                if let Err(e) = Book::new(0, name, pages_count, author_ids, isbn, &publisher) {
+                    errors.extend(e.iter().map(|e| e.to_string()));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(RowStatus::Valid)
+        } else {
+            Ok(RowStatus::Failed { errors })
+        }
+    }
+
+    async fn resolve(
+        &self,
+        record: &ImportRecord,
+        planned: &Planned,
+    ) -> Result<Result<Vec<i32>, Vec<String>>, ImportError> {
+        let mut ids = Vec::new();
+        let mut errors = Vec::new();
+        let refs = match record.author_refs() {
+            Ok(refs) => refs,
+            Err(e) => return Ok(Err(vec![e])),
+        };
+        for reference in &refs {
+            let reference = reference.as_str();
+            if let Some(id) = planned.resolve(reference) {
+                ids.push(id);
+                continue;
+            }
+
+            let by_key: Option<(i32,)> =
+                sqlx::query_as("select id from import_key where kind = 'author' and key = $1")
+                    .bind(reference)
+                    .fetch_optional(&self.pool)
+                    .await?;
+            if let Some((id,)) = by_key {
+                ids.push(id);
+                continue;
+            }
+
+            let by_name: Vec<(i32,)> =
+                sqlx::query_as("select id from author where full_name = $1 and not archived")
+                    .bind(reference)
+                    .fetch_all(&self.pool)
+                    .await?;
+            match by_name.as_slice() {
+                [(id,)] => ids.push(*id),
+                [] => errors.push(format!("unknown author `{}`", reference)),
+                _ => errors.push(format!(
+                    "author name `{}` is ambiguous, use a key",
+                    reference
+                )),
+            }
+        }
+
+        Ok(if errors.is_empty() {
+            Ok(ids)
+        } else {
+            Err(errors)
+        })
+    }
+
+    async fn imported(&self, record: &ImportRecord) -> Result<Option<i32>, ImportError> {
+        let Some(key) = &record.key else {
+            return Ok(None);
+        };
+        let id: Option<(i32,)> =
+            sqlx::query_as("select id from import_key where kind = $1 and key = $2")
+                .bind(record.kind.name())
+                .bind(key)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(id.map(|(id,)| id))
+    }
+
+    async fn position(&self, job: &str) -> Result<usize, ImportError> {
+        let position: Option<(i32,)> =
+            sqlx::query_as("select position from import_job where name = $1")
+                .bind(job)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(position.map_or(0, |(position,)| position as usize))
+    }
+
+    async fn failed_rows(&self, job: &str) -> Result<HashSet<usize>, ImportError> {
+        let rows: Vec<(i32,)> = sqlx::query_as("select row from import_failure where job = $1")
+            .bind(job)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|(row,)| row as usize).collect())
+    }
+
+    // For rows that are not created: moves the position past them and records their errors,
+    // or clears an earlier failure once they are skipped.
+    async fn save_row(
+        &self,
+        job: &str,
+        row: usize,
+        errors: Option<&[String]>,
+    ) -> Result<(), ImportError> {
+        let uow = DbUoW::new(self.pool.clone());
+        for statement in progress(job, row) {
+            uow.add(statement);
+        }
+        if let Some(errors) = errors {
+            uow.add(
+                Statement::new("insert into import_failure(job, row, errors) values ($1, $2, $3)")
+                    .bind(String::from(job))
+                    .bind(row as i32)
+                    .bind(errors.to_vec()),
+            );
+        }
+        Ok(uow.commit().await?)
+    }
+}
+
+// Moves the job position past `row` without ever moving it back, as retried rows lie
+// behind it, and clears the row's failure from an earlier run.
+fn progress(job: &str, row: usize) -> [Statement; 2] {
+    [
+        Statement::new(
+            "insert into import_job(name, position) values ($1, $2) \
+            on conflict (name) do update set position = greatest(import_job.position, excluded.position)",
+        )
+        .bind(String::from(job))
+        .bind(row as i32),
+        Statement::new("delete from import_failure where job = $1 and row = $2")
+            .bind(String::from(job))
+            .bind(row as i32),
+    ]
+}
+
+// Authors a dry run would have created, so that later book rows can refer to them. They
+// get negative placeholder ids. ISBNs are tracked so duplicates within the file show up.
+#[derive(Default)]
+struct Planned {
+    by_ref: HashMap<String, i32>,
+    isbns: HashSet<Isbn>,
+}
+
+impl Planned {
+    fn add(&mut self, key: Option<&str>, full_name: &str) {
+        let id = -(self.by_ref.len() as i32) - 1;
+        if let Some(key) = key {
+            self.by_ref.insert(String::from(key), id);
+        }
+        self.by_ref.entry(String::from(full_name)).or_insert(id);
+    }
+
+    fn resolve(&self, reference: &str) -> Option<i32> {
+        self.by_ref.get(reference).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CSV: &str = "\
+kind,key,first_name,last_name,name,pages_count,isbn,authors
+author,tp,Terry,Pratchett,,,,
+author,,Neil,Gaiman,,,,
+book,mort,,,Mort,243,0-552-13106-7,tp
+book,,,,Good Omens,412,,tp; Neil Gaiman
+book,,,,Nameless,0,0-552-13106-7,nobody
+";
+
+    #[test]
+    fn read() {
+        let csv = read_csv(CSV.as_bytes()).unwrap();
+        let json = read_json(
+            r#"[
+                {"kind": "author", "key": "tp", "first_name": "Terry", "last_name": "Pratchett"},
+                {"kind": "book", "name": "Good Omens", "pages_count": 412, "authors": ["tp", "Neil Gaiman"]}
+            ]"#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            (
+                csv[0].kind,
+                &csv[0].key,
+                &csv[0].first_name,
+                &csv[0].last_name
+            ),
+            (
+                json[0].kind,
+                &json[0].key,
+                &json[0].first_name,
+                &json[0].last_name
+            )
+        );
+        assert!(csv[0].author_refs().unwrap().is_empty());
+        assert_eq!(csv[3].author_refs().unwrap(), vec!["tp", "Neil Gaiman"]);
+        assert_eq!(json[1].author_refs().unwrap(), vec!["tp", "Neil Gaiman"]);
+        assert_eq!(csv[1].key, None);
+    }
+
+    #[test]
+    fn lists() {
+        let items = ["tp", "Smith; Jones", "The \"Doc\"", " padded", ""];
+
+        let list = join_list(items);
+
+        assert_eq!(list, r#"tp;"Smith; Jones";"The ""Doc""";" padded";"#);
+        assert_eq!(split_list(&list).unwrap(), items);
+        assert_eq!(
+            split_list(r#" tp ; "a;b" ;c"#).unwrap(),
+            vec!["tp", "a;b", "c"]
+        );
+        assert!(split_list(r#"tp;"a;b"#).is_err());
+        assert!(split_list(r#""a"b"#).is_err());
+    }
+
+    #[sqlx::test]
+    async fn dry_run(pool: PgPool) {
+        let records = read_csv(CSV.as_bytes()).unwrap();
+
+        let report = Importer::new(pool.clone())
+            .dry_run()
+            .import("catalog", &records)
+            .await
+            .unwrap();
+
+        let statuses: Vec<&RowStatus> = report.rows.iter().map(|r| &r.status).collect();
+        assert_eq!(statuses[..4], [&RowStatus::Valid; 4]);
+        assert_eq!(
+            statuses[4],
+            &RowStatus::Failed {
+                errors: vec![
+                    String::from("unknown author `nobody`"),
+                    String::from("ISBN 978-0-552-13106-3 is already used by another book"),
+                    String::from("pages count must be positive, got 0")
+                ]
+            }
+        );
+        let (authors,): (i64,) = sqlx::query_as("select count(*) from author")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(authors, 0);
+    }
+
+    #[sqlx::test]
+    async fn import_and_resume(pool: PgPool) {
+        let records = read_csv(CSV.as_bytes()).unwrap();
+        let importer = Importer::new(pool.clone());
+
+        let report = importer.import("catalog", &records[..3]).await.unwrap();
+        assert_eq!(report.failed(), 0);
+        assert_eq!(report.rows[2].status, RowStatus::Created { id: 1 });
+
+        let report = importer.import("catalog", &records).await.unwrap();
+        assert_eq!(report.resumed_at, 3);
+        let statuses: Vec<&RowStatus> = report.rows.iter().map(|r| &r.status).collect();
+        assert_eq!(statuses[0], &RowStatus::Created { id: 2 });
+        assert!(matches!(statuses[1], RowStatus::Failed { .. }));
+
+        let authors: Vec<(i32,)> = sqlx::query_as(
+            "select author_id from author_book where book_id = 2 order by author_id",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(authors, vec![(1,), (2,)]);
+
+        let (events,): (i64,) = sqlx::query_as("select count(*) from stored_event")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(events, 4);
+
+        let corrected = read_csv(
+            CSV.replace(
+                "Nameless,0,0-552-13106-7,nobody",
+                "Nameless,10,,Neil Gaiman",
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let report = importer.import("catalog", &corrected).await.unwrap();
+        assert_eq!(report.resumed_at, 5);
+        assert_eq!(report.rows.len(), 1);
+        assert_eq!(report.rows[0].row, 5);
+        assert_eq!(report.rows[0].status, RowStatus::Created { id: 3 });
+        assert!(importer
+            .import("catalog", &corrected)
+            .await
+            .unwrap()
+            .rows
+            .is_empty());
+
+        let (keys,): (i64,) = sqlx::query_as("select count(*) from import_key")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(keys, 2);
+
+        sqlx::query("delete from import_job")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let report = importer.import("catalog", &records[..1]).await.unwrap();
+        assert_eq!(report.rows[0].status, RowStatus::Skipped { id: 1 });
+    }
+}
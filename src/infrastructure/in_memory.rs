@@ -0,0 +1,612 @@
+use crate::{
+    application::{BoxError, CommitError, EventStore, StoredEvent, UoW},
+    domain::{
+        author::{Author, AuthorRepository},
+        book::{Book, BookRepository},
+        isbn::Isbn,
+        Aggregate, DomainEvent, DomainEventPublisher,
+    },
+};
+use async_trait::async_trait;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+#[derive(Clone)]
+struct BookRow {
+    id: i32,
+    name: String,
+    pages_count: i32,
+    authors: Vec<i32>,
+    isbn: Option<Isbn>,
+    archived: bool,
+    version: i32,
+}
+
+#[derive(Clone)]
+struct AuthorRow {
+    id: i32,
+    first_name: String,
+    last_name: String,
+    full_name: String,
+    archived: bool,
+    version: i32,
+}
+
+#[derive(Clone, Default)]
+struct State {
+    books: BTreeMap<i32, BookRow>,
+    authors: BTreeMap<i32, AuthorRow>,
+    events: Vec<StoredEvent>,
+    book_id_seq: i32,
+    author_id_seq: i32,
+    transaction_seq: i64,
+}
+
+// Shared the way a PgPool is: every unit of work created from clones of one database sees
+// the same committed state.
+#[derive(Clone, Default)]
+pub struct InMemoryDatabase {
+    state: Arc<RwLock<State>>,
+}
+
+impl InMemoryDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> Vec<StoredEvent> {
+        self.state.read().unwrap().events.clone()
+    }
+}
+
+enum Change {
+    Book {
+        row: BookRow,
+        expected_version: Option<i32>,
+    },
+    Author {
+        row: AuthorRow,
+        expected_version: Option<i32>,
+    },
+    Event {
+        aggregate_type: &'static str,
+        aggregate_id: i32,
+        name: &'static str,
+        payload: String,
+    },
+}
+
+impl Change {
+    fn describe(&self) -> String {
+        match self {
+            Change::Book {
+                row,
+                expected_version: None,
+            } => format!("insert book {}", row.id),
+            Change::Book { row, .. } => format!("update book {}", row.id),
+            Change::Author {
+                row,
+                expected_version: None,
+            } => format!("insert author {}", row.id),
+            Change::Author { row, .. } => format!("update author {}", row.id),
+            Change::Event {
+                aggregate_type,
+                aggregate_id,
+                name,
+                ..
+            } => format!("append {} to {} {}", name, aggregate_type, aggregate_id),
+        }
+    }
+}
+
+pub struct InMemoryUoW {
+    db: InMemoryDatabase,
+    changes: RwLock<Vec<Change>>,
+}
+
+impl InMemoryUoW {
+    pub fn new(db: InMemoryDatabase) -> Self {
+        Self {
+            db,
+            changes: RwLock::new(Vec::new()),
+        }
+    }
+
+    fn add(&self, change: Change) {
+        self.changes.write().unwrap().push(change);
+    }
+}
+
+#[async_trait]
+impl UoW for InMemoryUoW {
+    // Changes are applied to a copy of the committed state, which replaces it only when
+    // every change succeeds, mirroring the transaction DbUoW runs its statements in.
+    async fn commit(&self) -> Result<(), CommitError> {
+        let changes = std::mem::take(&mut *self.changes.write().unwrap());
+
+        let mut state = self.db.state.write().unwrap();
+        let mut next = state.clone();
+        next.transaction_seq += 1;
+
+        for (index, change) in changes.into_iter().enumerate() {
+            let sql = change.describe();
+            match change {
+                Change::Book {
+                    row,
+                    expected_version,
+                } => {
+                    let current = next.books.get(&row.id).map(|b| b.version);
+                    match (current, expected_version) {
+                        (None, None) => {}
+                        (Some(_), None) => {
+                            return Err(CommitError::Statement {
+                                index,
+                                sql,
+                                source: format!("book {} already exists", row.id).into(),
+                            });
+                        }
+                        (current, Some(expected)) if current != Some(expected) => {
+                            return Err(CommitError::Conflict { index, sql });
+                        }
+                        _ => {}
+                    }
+                    // book_author references author, as in the database.
+                    if let Some(author) = row.authors.iter().find(|a| !next.authors.contains_key(a))
+                    {
+                        return Err(CommitError::Statement {
+                            index,
+                            sql,
+                            source: format!("author {} does not exist", author).into(),
+                        });
+                    }
+                    if row.isbn.is_some()
+                        && next
+                            .books
+                            .values()
+                            .any(|b| b.id != row.id && b.isbn == row.isbn)
+                    {
+                        return Err(CommitError::Conflict { index, sql });
+                    }
+                    next.books.insert(row.id, row);
+                }
+                Change::Author {
+                    row,
+                    expected_version,
+                } => {
+                    let current = next.authors.get(&row.id).map(|a| a.version);
+                    match (current, expected_version) {
+                        (None, None) => {}
+                        (Some(_), None) => {
+                            return Err(CommitError::Statement {
+                                index,
+                                sql,
+                                source: format!("author {} already exists", row.id).into(),
+                            });
+                        }
+                        (current, Some(expected)) if current != Some(expected) => {
+                            return Err(CommitError::Conflict { index, sql });
+                        }
+                        _ => {}
+                    }
+                    next.authors.insert(row.id, row);
+                }
+                Change::Event {
+                    aggregate_type,
+                    aggregate_id,
+                    name,
+                    payload,
+                } => {
+                    let sequence = next
+                        .events
+                        .iter()
+                        .filter(|e| {
+                            e.aggregate_type() == aggregate_type && e.aggregate_id() == aggregate_id
+                        })
+                        .count() as i32
+                        + 1;
+                    let id = next.events.len() as i32 + 1;
+                    next.events.push(StoredEvent::new(
+                        id,
+                        next.transaction_seq,
+                        aggregate_type,
+                        aggregate_id,
+                        sequence,
+                        name,
+                        &payload,
+                    ));
+                }
+            }
+        }
+
+        *state = next;
+        Ok(())
+    }
+
+    fn rollback(&self) {
+        self.changes.write().unwrap().clear();
+    }
+}
+
+pub struct InMemoryEventStore<'a> {
+    db: &'a InMemoryUoW,
+}
+
+impl<'a> InMemoryEventStore<'a> {
+    pub fn new(db: &'a InMemoryUoW) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl<'a> EventStore for InMemoryEventStore<'a> {
+    fn append(&mut self, domain_event: &DomainEvent) {
+        let (aggregate, id) = domain_event.stream();
+        let payload = serde_json::to_string(domain_event).expect("domain_event serialized");
+
+        self.db.add(Change::Event {
+            aggregate_type: aggregate.name(),
+            aggregate_id: id,
+            name: domain_event.domain_event_name(),
+            payload,
+        });
+    }
+
+    async fn load_stream(
+        &self,
+        aggregate: Aggregate,
+        id: i32,
+    ) -> Result<Vec<StoredEvent>, BoxError> {
+        Ok(self
+            .db
+            .db
+            .events()
+            .into_iter()
+            .filter(|e| e.aggregate_type() == aggregate.name() && e.aggregate_id() == id)
+            .collect())
+    }
+}
+
+pub struct InMemoryBookRepository<'a, 'b, 'c> {
+    db: &'a InMemoryUoW,
+    publisher: &'b DomainEventPublisher<'c>,
+}
+
+impl<'a, 'b, 'c> InMemoryBookRepository<'a, 'b, 'c> {
+    pub fn new(db: &'a InMemoryUoW, publisher: &'b DomainEventPublisher<'c>) -> Self {
+        Self { db, publisher }
+    }
+
+    fn save(&self, book: &Book, expected_version: Option<i32>) {
+        self.db.add(Change::Book {
+            row: BookRow {
+                id: book.id(),
+                name: String::from(book.name()),
+                pages_count: book.pages_count(),
+                authors: book.authors().to_vec(),
+                isbn: book.isbn().cloned(),
+                archived: book.archived(),
+                version: book.next_version(),
+            },
+            expected_version,
+        });
+    }
+
+    fn materialize(&self, row: &BookRow) -> Book<'b, 'c> {
+        Book::materialize(
+            row.id,
+            &row.name,
+            row.pages_count,
+            row.authors.clone(),
+            row.isbn.clone(),
+            row.archived,
+            row.version,
+            self.publisher,
+        )
+    }
+}
+
+#[async_trait]
+impl<'a, 'b, 'c> BookRepository<'b, 'c> for InMemoryBookRepository<'a, 'b, 'c> {
+    fn create(&self, book: &Book) {
+        self.save(book, None);
+    }
+
+    fn update(&self, book: &Book) {
+        self.save(book, Some(book.version()));
+    }
+
+    fn archive(&self, book: &Book) {
+        self.save(book, Some(book.version()));
+    }
+
+    fn restore(&self, book: &Book) {
+        self.save(book, Some(book.version()));
+    }
+
+    async fn next_identity(&self) -> i32 {
+        let mut state = self.db.db.state.write().unwrap();
+        state.book_id_seq += 1;
+        state.book_id_seq
+    }
+
+    async fn by_id(&self, id: i32) -> Result<Option<Book<'b, 'c>>, BoxError> {
+        let state = self.db.db.state.read().unwrap();
+        Ok(state.books.get(&id).map(|row| self.materialize(row)))
+    }
+
+    async fn id_by_isbn(&self, isbn: &Isbn) -> Option<i32> {
+        let state = self.db.db.state.read().unwrap();
+        state
+            .books
+            .values()
+            .find(|row| row.isbn.as_ref() == Some(isbn))
+            .map(|row| row.id)
+    }
+
+    async fn active_by_author(&self, author_id: i32) -> Result<Vec<Book<'b, 'c>>, BoxError> {
+        let state = self.db.db.state.read().unwrap();
+        Ok(state
+            .books
+            .values()
+            .filter(|row| !row.archived && row.authors.contains(&author_id))
+            .map(|row| self.materialize(row))
+            .collect())
+    }
+}
+
+pub struct InMemoryAuthorRepository<'a, 'b, 'c> {
+    db: &'a InMemoryUoW,
+    publisher: &'b DomainEventPublisher<'c>,
+}
+
+impl<'a, 'b, 'c> InMemoryAuthorRepository<'a, 'b, 'c> {
+    pub fn new(db: &'a InMemoryUoW, publisher: &'b DomainEventPublisher<'c>) -> Self {
+        Self { db, publisher }
+    }
+
+    fn save(&self, author: &Author, expected_version: Option<i32>) {
+        self.db.add(Change::Author {
+            row: AuthorRow {
+                id: author.id(),
+                first_name: String::from(author.first_name()),
+                last_name: String::from(author.last_name()),
+                full_name: String::from(author.full_name()),
+                archived: author.archived(),
+                version: author.next_version(),
+            },
+            expected_version,
+        });
+    }
+}
+
+#[async_trait]
+impl<'a, 'b, 'c> AuthorRepository<'b, 'c> for InMemoryAuthorRepository<'a, 'b, 'c> {
+    fn create(&self, author: &Author) {
+        self.save(author, None);
+    }
+
+    fn update(&self, author: &Author) {
+        self.save(author, Some(author.version()));
+    }
+
+    fn archive(&self, author: &Author) {
+        self.save(author, Some(author.version()));
+    }
+
+    fn restore(&self, author: &Author) {
+        self.save(author, Some(author.version()));
+    }
+
+    async fn next_identity(&self) -> i32 {
+        let mut state = self.db.db.state.write().unwrap();
+        state.author_id_seq += 1;
+        state.author_id_seq
+    }
+
+    async fn by_id(&self, id: i32) -> Result<Option<Author<'b, 'c>>, BoxError> {
+        let state = self.db.db.state.read().unwrap();
+        Ok(state.authors.get(&id).map(|row| {
+            Author::materialize(
+                row.id,
+                &row.first_name,
+                &row.last_name,
+                &row.full_name,
+                row.archived,
+                row.version,
+                self.publisher,
+            )
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::application::{self, ApplicationError};
+
+    async fn create_author(db: &InMemoryDatabase, first_name: &str, last_name: &str) {
+        let uow = InMemoryUoW::new(db.clone());
+        let mut event_store = InMemoryEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = InMemoryAuthorRepository::new(&uow, &publisher);
+
+        application::author::create(
+            first_name,
+            last_name,
+            &publisher,
+            &repo,
+            &mut event_store,
+            &uow,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[async_std::test]
+    async fn create_and_update() {
+        let db = InMemoryDatabase::new();
+        create_author(&db, "f1", "l1").await;
+
+        let uow = InMemoryUoW::new(db.clone());
+        let mut event_store = InMemoryEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = InMemoryBookRepository::new(&uow, &publisher);
+        let authors = InMemoryAuthorRepository::new(&uow, &publisher);
+        application::book::create(
+            "book1",
+            100,
+            vec![1],
+            None,
+            &publisher,
+            &repo,
+            &authors,
+            &mut event_store,
+            &uow,
+        )
+        .await
+        .unwrap();
+
+        let uow = InMemoryUoW::new(db.clone());
+        let mut event_store = InMemoryEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = InMemoryBookRepository::new(&uow, &publisher);
+        let authors = InMemoryAuthorRepository::new(&uow, &publisher);
+        application::book::update(
+            1,
+            "book1-renamed",
+            120,
+            vec![1],
+            None,
+            &publisher,
+            &repo,
+            &authors,
+            &mut event_store,
+            &uow,
+        )
+        .await
+        .unwrap();
+
+        let book = repo.by_id(1).await.unwrap().unwrap();
+        assert_eq!(book.name(), "book1-renamed");
+        assert_eq!(book.version(), 3);
+
+        let names: Vec<String> = db.events().iter().map(|e| String::from(e.name())).collect();
+        assert_eq!(
+            names,
+            vec![
+                "author_created",
+                "book_created",
+                "book_renamed",
+                "book_pages_count_changed"
+            ]
+        );
+        let stream = InMemoryEventStore::new(&uow)
+            .load_stream(Aggregate::Book, 1)
+            .await
+            .unwrap();
+        let sequences: Vec<i32> = stream.iter().map(|e| e.sequence()).collect();
+        assert_eq!(sequences, vec![1, 2, 3]);
+    }
+
+    #[async_std::test]
+    async fn failed_validation_leaves_nothing_behind() {
+        let db = InMemoryDatabase::new();
+        let uow = InMemoryUoW::new(db.clone());
+        let mut event_store = InMemoryEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = InMemoryBookRepository::new(&uow, &publisher);
+        let authors = InMemoryAuthorRepository::new(&uow, &publisher);
+
+        let result = application::book::create(
+            "",
+            100,
+            vec![1],
+            None,
+            &publisher,
+            &repo,
+            &authors,
+            &mut event_store,
+            &uow,
+        )
+        .await;
+        assert!(matches!(result, Err(ApplicationError::Domain(_))));
+
+        uow.rollback();
+        uow.commit().await.unwrap();
+        assert!(db.events().is_empty());
+        assert!(repo.by_id(1).await.unwrap().is_none());
+    }
+
+    #[async_std::test]
+    async fn update_conflict() {
+        let db = InMemoryDatabase::new();
+        create_author(&db, "f1", "l1").await;
+
+        let uow = InMemoryUoW::new(db.clone());
+        let publisher = DomainEventPublisher::new();
+        let repo = InMemoryAuthorRepository::new(&uow, &publisher);
+        let mut first = repo.by_id(1).await.unwrap().unwrap();
+        let mut second = repo.by_id(1).await.unwrap().unwrap();
+
+        first.update("f2", "l1").unwrap();
+        repo.update(&first);
+        uow.commit().await.unwrap();
+
+        second.update("f3", "l1").unwrap();
+        repo.update(&second);
+        let result = uow.commit().await;
+
+        assert!(matches!(
+            result,
+            Err(CommitError::Conflict { index: 0, .. })
+        ));
+        assert_eq!(repo.by_id(1).await.unwrap().unwrap().first_name(), "f2");
+    }
+
+    #[async_std::test]
+    async fn failed_commit_discards_every_change() {
+        let db = InMemoryDatabase::new();
+        create_author(&db, "f1", "l1").await;
+
+        let uow = InMemoryUoW::new(db.clone());
+        let mut event_store = InMemoryEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        let repo = InMemoryAuthorRepository::new(&uow, &publisher);
+        let author = Author::new(1, "f2", "l2", &publisher).unwrap();
+        event_store.append(&DomainEvent::AuthorRestored(
+            crate::domain::author::AuthorRestored { id: 1 },
+        ));
+        repo.create(&author);
+
+        let result = uow.commit().await;
+
+        assert!(matches!(
+            result,
+            Err(CommitError::Statement { index: 1, .. })
+        ));
+        assert_eq!(db.events().len(), 1);
+        assert!(uow.changes.read().unwrap().is_empty());
+    }
+
+    #[async_std::test]
+    async fn book_authors_must_exist() {
+        let db = InMemoryDatabase::new();
+        create_author(&db, "f1", "l1").await;
+
+        let uow = InMemoryUoW::new(db.clone());
+        let publisher = DomainEventPublisher::new();
+        let repo = InMemoryBookRepository::new(&uow, &publisher);
+        let book = Book::new(1, "book1", 100, vec![1, 7], None, &publisher).unwrap();
+        repo.create(&book);
+
+        let result = uow.commit().await;
+
+        assert!(matches!(
+            result,
+            Err(CommitError::Statement { index: 0, .. })
+        ));
+        assert!(repo.by_id(1).await.unwrap().is_none());
+    }
+}
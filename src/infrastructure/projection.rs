@@ -0,0 +1,630 @@
+use super::{committed_events_after, count_events_after, events_after, relay::RELAY_LOCK};
+use crate::{
+    application::{BoxError, EventHandler, Position},
+    domain::DomainEventPublisher,
+};
+use async_std::{future, task};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    StreamExt,
+};
+use sqlx::{Executor, PgPool, Postgres};
+use std::{error::Error, fmt, time::Duration};
+
+// Held for the length of a rebuild, keyed by projection name as well, so a second rebuild of
+// the same projection waits instead of dropping the shadow schema under the first.
+const REBUILD_LOCK: i32 = 0x72656275;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayProgress {
+    pub position: Position,
+    pub processed: i64,
+    pub total: i64,
+}
+
+#[derive(Debug)]
+pub enum ProjectionError {
+    Database(sqlx::Error),
+    Handler { event_id: i32, source: BoxError },
+}
+
+impl fmt::Display for ProjectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectionError::Database(e) => write!(f, "projection database error: {}", e),
+            ProjectionError::Handler { event_id, source } => {
+                write!(
+                    f,
+                    "projection failed on stored_event {}: {}",
+                    event_id, source
+                )
+            }
+        }
+    }
+}
+
+impl Error for ProjectionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProjectionError::Database(e) => Some(e),
+            ProjectionError::Handler { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ProjectionError {
+    fn from(e: sqlx::Error) -> Self {
+        ProjectionError::Database(e)
+    }
+}
+
+pub struct ProjectionRunner {
+    pool: PgPool,
+    batch_size: i64,
+}
+
+impl ProjectionRunner {
+    pub fn new(pool: PgPool, batch_size: i64) -> Self {
+        Self { pool, batch_size }
+    }
+
+    // Replays every event into empty copies of `tables` in a shadow schema while the live
+    // tables keep serving reads, then swaps the copies in and moves the checkpoint within one
+    // transaction. `projection` receives a pool whose search_path resolves to the shadow
+    // copies.
+    //
+    // The swap transaction holds the relay lock, so no relay writes to the live tables
+    // between the last catch-up and the swap; events committed after the lock was taken are
+    // still undispatched and reach the swapped-in tables.
+    //
+    // Each projection gets its own shadow schema, so different projections can rebuild at
+    // the same time.
+    pub async fn rebuild<P: EventHandler>(
+        &self,
+        name: &str,
+        tables: &[&str],
+        projection: impl FnOnce(PgPool) -> P,
+        progress: &mut (dyn FnMut(ReplayProgress) + Send),
+    ) -> Result<Position, ProjectionError> {
+        // Rolled back, and so released, when the rebuild returns, whether it succeeded or not.
+        let mut guard = self.pool.begin().await?;
+        sqlx::query("select pg_advisory_xact_lock($1, hashtext($2))")
+            .bind(REBUILD_LOCK)
+            .bind(name)
+            .execute(&mut *guard)
+            .await?;
+
+        let schema = shadow_schema(name);
+        let mut sql = format!(
+            "drop schema if exists {0} cascade; create schema {0};",
+            schema
+        );
+        for table in quoted(tables) {
+            sql.push_str(&format!(
+                "create table {0}.{1} (like public.{1} including all);",
+                schema, table
+            ));
+        }
+        self.pool.execute(&*sql).await?;
+
+        let options = (*self.pool.connect_options())
+            .clone()
+            .options([("search_path", format!("{},public", schema))]);
+        let shadow_pool = PgPool::connect_with(options).await?;
+        let projection = projection(shadow_pool.clone());
+
+        let mut position = self
+            .replay_from(Position::default(), &projection, progress)
+            .await?;
+        loop {
+            let caught_up = self.replay_from(position, &projection, progress).await?;
+            if caught_up == position {
+                break;
+            }
+            position = caught_up;
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("select pg_advisory_xact_lock($1)")
+            .bind(RELAY_LOCK)
+            .execute(&mut *tx)
+            .await?;
+        position = self.replay_from(position, &projection, progress).await?;
+        // The relay may already have delivered events that an earlier, still running
+        // transaction holds back from the replay; they are applied too, but the checkpoint
+        // stays behind them so catch-up readers get them again in commit order.
+        self.apply_committed_after(position, &projection).await?;
+        shadow_pool.close().await;
+
+        let mut sql = String::new();
+        for table in quoted(tables) {
+            sql.push_str(&format!(
+                "drop table public.{1}; alter table {0}.{1} set schema public;",
+                schema, table
+            ));
+        }
+        sql.push_str(&format!("drop schema {};", schema));
+        tx.execute(&*sql).await?;
+        save_checkpoint(&mut *tx, name, position).await?;
+        tx.commit().await?;
+        guard.rollback().await?;
+
+        Ok(position)
+    }
+
+    async fn apply_committed_after(
+        &self,
+        mut position: Position,
+        projection: &dyn EventHandler,
+    ) -> Result<(), ProjectionError> {
+        loop {
+            let events = committed_events_after(&self.pool, position, self.batch_size).await?;
+            if events.is_empty() {
+                return Ok(());
+            }
+
+            for event in events {
+                projection
+                    .handle(&event)
+                    .await
+                    .map_err(|source| ProjectionError::Handler {
+                        event_id: event.id(),
+                        source,
+                    })?;
+                position = event.position();
+            }
+        }
+    }
+
+    async fn replay_from(
+        &self,
+        mut position: Position,
+        projection: &dyn EventHandler,
+        progress: &mut (dyn FnMut(ReplayProgress) + Send),
+    ) -> Result<Position, ProjectionError> {
+        let total = count_events_after(&self.pool, position).await?;
+
+        let mut processed = 0;
+        loop {
+            let events = events_after(&self.pool, position, self.batch_size).await?;
+            if events.is_empty() {
+                return Ok(position);
+            }
+
+            for event in events {
+                projection
+                    .handle(&event)
+                    .await
+                    .map_err(|source| ProjectionError::Handler {
+                        event_id: event.id(),
+                        source,
+                    })?;
+                position = event.position();
+                processed += 1;
+            }
+
+            progress(ReplayProgress {
+                position,
+                processed,
+                total: total.max(processed),
+            });
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LiveNotifier {
+    sender: UnboundedSender<()>,
+}
+
+impl LiveNotifier {
+    // Notifies after the commit, once the events can be read.
+    pub fn attach(&self, publisher: &DomainEventPublisher) {
+        let sender = self.sender.clone();
+        publisher.subscribe_after_commit(move |_| {
+            let _ = sender.unbounded_send(());
+        });
+    }
+}
+
+pub struct LiveEvents {
+    receiver: UnboundedReceiver<()>,
+}
+
+pub fn live() -> (LiveNotifier, LiveEvents) {
+    let (sender, receiver) = mpsc::unbounded();
+    (LiveNotifier { sender }, LiveEvents { receiver })
+}
+
+pub struct CatchUpSubscription<H> {
+    pool: PgPool,
+    name: String,
+    handler: H,
+    batch_size: i64,
+}
+
+impl<H: EventHandler> CatchUpSubscription<H> {
+    pub fn new(pool: PgPool, name: &str, handler: H, batch_size: i64) -> Self {
+        Self {
+            pool,
+            name: String::from(name),
+            handler,
+            batch_size,
+        }
+    }
+
+    pub async fn checkpoint(&self) -> Result<Position, ProjectionError> {
+        let position: Option<(i64, i32)> = sqlx::query_as(
+            "select transaction_id, position from projection_checkpoint where name = $1",
+        )
+        .bind(&self.name)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(
+            position.map_or(Position::default(), |(transaction_id, event_id)| Position {
+                transaction_id,
+                event_id,
+            }),
+        )
+    }
+
+    pub async fn catch_up(&self) -> Result<Position, ProjectionError> {
+        let mut position = self.checkpoint().await?;
+        loop {
+            let events = events_after(&self.pool, position, self.batch_size).await?;
+            if events.is_empty() {
+                return Ok(position);
+            }
+
+            for event in events {
+                self.handler
+                    .handle(&event)
+                    .await
+                    .map_err(|source| ProjectionError::Handler {
+                        event_id: event.id(),
+                        source,
+                    })?;
+                position = event.position();
+            }
+            save_checkpoint(&self.pool, &self.name, position).await?;
+        }
+    }
+
+    // Live notifications only wake the subscription up early; events held back behind a
+    // transaction that was still running are picked up by the next poll.
+    pub async fn run(
+        &self,
+        mut live: LiveEvents,
+        poll_interval: Duration,
+    ) -> Result<(), ProjectionError> {
+        self.catch_up().await?;
+        loop {
+            match future::timeout(poll_interval, live.receiver.next()).await {
+                Ok(Some(())) => while live.receiver.try_recv().is_ok() {},
+                Ok(None) => task::sleep(poll_interval).await,
+                Err(_) => {}
+            }
+            self.catch_up().await?;
+        }
+    }
+}
+
+async fn save_checkpoint<'e>(
+    executor: impl Executor<'e, Database = Postgres>,
+    name: &str,
+    position: Position,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "insert into projection_checkpoint(name, transaction_id, position) values ($1, $2, $3) \
+        on conflict (name) do update set transaction_id = excluded.transaction_id, position = excluded.position",
+    )
+    .bind(name)
+    .bind(position.transaction_id)
+    .bind(position.event_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+fn quoted(tables: &[&str]) -> Vec<String> {
+    tables.iter().map(|t| quote(t)).collect()
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn shadow_schema(name: &str) -> String {
+    quote(&format!("projection_rebuild_{}", name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::application::StoredEvent;
+    use crate::{
+        application::{self, book_listing::BookListing, book_projector::BookProjector},
+        domain::DomainEventPublisher,
+        infrastructure::{
+            author::DbAuthorRepository, book::DbBookRepository, book_listing::DbBookListing,
+            DbEventStore, DbUoW,
+        },
+    };
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+
+    async fn seed_author(pool: &PgPool, notifier: Option<&LiveNotifier>) {
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        let publisher = DomainEventPublisher::new();
+        if let Some(notifier) = notifier {
+            notifier.attach(&publisher);
+        }
+        let authors = DbAuthorRepository::new(&uow, &publisher);
+        application::author::create("f2", "l2", &publisher, &authors, &mut event_store, &uow)
+            .await
+            .unwrap();
+    }
+
+    async fn seed(pool: &PgPool) {
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        {
+            let publisher = DomainEventPublisher::new();
+            let authors = DbAuthorRepository::new(&uow, &publisher);
+            application::author::create("f1", "l1", &publisher, &authors, &mut event_store, &uow)
+                .await
+                .unwrap();
+        }
+        {
+            let publisher = DomainEventPublisher::new();
+            let books = DbBookRepository::new(&uow, &publisher);
+            let authors = DbAuthorRepository::new(&uow, &publisher);
+            application::book::create(
+                "book1",
+                100,
+                vec![1],
+                None,
+                &publisher,
+                &books,
+                &authors,
+                &mut event_store,
+                &uow,
+            )
+            .await
+            .unwrap();
+        }
+    }
+
+    #[sqlx::test]
+    async fn rebuild(pool: PgPool) {
+        seed(&pool).await;
+        sqlx::query("insert into book_listing(id, name, pages_count, author_ids, authors) values (99, 'stale', 1, '{}', '')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let runner = ProjectionRunner::new(pool.clone(), 1);
+        let mut reports = Vec::new();
+        let position = runner
+            .rebuild(
+                "book_projector",
+                DbBookListing::TABLES,
+                |pool| BookProjector::new(DbBookListing::new(pool)),
+                &mut |p| reports.push(p),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(position.event_id, 2);
+        assert_eq!(reports.last().unwrap().processed, 2);
+        assert_eq!(reports.last().unwrap().total, 2);
+
+        let books = DbBookListing::new(pool.clone()).list().await;
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].name, "book1");
+        assert_eq!(books[0].authors, "f1 l1");
+
+        let (schemas,): (i64,) = sqlx::query_as(
+            "select count(*) from information_schema.schemata where schema_name like 'projection_rebuild%'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(schemas, 0);
+    }
+
+    struct Recorder {
+        events: Arc<Mutex<Vec<i32>>>,
+    }
+
+    #[async_trait]
+    impl EventHandler for Recorder {
+        async fn handle(&self, event: &StoredEvent) -> Result<(), BoxError> {
+            self.events.lock().unwrap().push(event.id());
+            Ok(())
+        }
+    }
+
+    #[sqlx::test]
+    async fn catch_up_resumes_from_checkpoint(pool: PgPool) {
+        seed(&pool).await;
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let subscription = CatchUpSubscription::new(
+            pool.clone(),
+            "recorder",
+            Recorder {
+                events: events.clone(),
+            },
+            1,
+        );
+        assert_eq!(subscription.catch_up().await.unwrap().event_id, 2);
+        assert_eq!(subscription.checkpoint().await.unwrap().event_id, 2);
+
+        seed_author(&pool, None).await;
+
+        let restarted = CatchUpSubscription::new(
+            pool,
+            "recorder",
+            Recorder {
+                events: events.clone(),
+            },
+            1,
+        );
+        assert_eq!(restarted.catch_up().await.unwrap().event_id, 3);
+        assert_eq!(*events.lock().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[sqlx::test]
+    async fn run_switches_to_live_events(pool: PgPool) {
+        seed(&pool).await;
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let (notifier, live_events) = live();
+
+        let subscription = CatchUpSubscription::new(
+            pool.clone(),
+            "recorder",
+            Recorder {
+                events: events.clone(),
+            },
+            10,
+        );
+        let running =
+            task::spawn(
+                async move { subscription.run(live_events, Duration::from_secs(60)).await },
+            );
+
+        seed_author(&pool, Some(&notifier)).await;
+
+        for _ in 0..250 {
+            if events.lock().unwrap().len() == 3 {
+                break;
+            }
+            task::sleep(Duration::from_millis(20)).await;
+        }
+        running.cancel().await;
+
+        assert_eq!(*events.lock().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[sqlx::test]
+    async fn concurrent_rebuilds(pool: PgPool) {
+        seed(&pool).await;
+        let runner = ProjectionRunner::new(pool.clone(), 1);
+        let (mut first, mut second) = (|_| {}, |_| {});
+
+        let (first, second) = futures::join!(
+            runner.rebuild(
+                "book_projector",
+                DbBookListing::TABLES,
+                |pool| BookProjector::new(DbBookListing::new(pool)),
+                &mut first,
+            ),
+            runner.rebuild(
+                "book_projector",
+                DbBookListing::TABLES,
+                |pool| BookProjector::new(DbBookListing::new(pool)),
+                &mut second,
+            )
+        );
+
+        assert_eq!(first.unwrap().event_id, 2);
+        assert_eq!(second.unwrap().event_id, 2);
+        let books = DbBookListing::new(pool).list().await;
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].name, "book1");
+    }
+
+    #[sqlx::test]
+    async fn rebuild_keeps_events_held_back_by_earlier_transactions(pool: PgPool) {
+        seed(&pool).await;
+
+        let mut slow = pool.begin().await.unwrap();
+        sqlx::query(
+            "insert into stored_event(aggregate_type, aggregate_id, sequence, name, payload) \
+            values ('author', 9, 1, 'author_created', '{}')",
+        )
+        .execute(&mut *slow)
+        .await
+        .unwrap();
+        {
+            let uow = DbUoW::new(pool.clone());
+            let mut event_store = DbEventStore::new(&uow);
+            let publisher = DomainEventPublisher::new();
+            let books = DbBookRepository::new(&uow, &publisher);
+            let authors = DbAuthorRepository::new(&uow, &publisher);
+            application::book::update(
+                1,
+                "book2",
+                100,
+                vec![1],
+                None,
+                &publisher,
+                &books,
+                &authors,
+                &mut event_store,
+                &uow,
+            )
+            .await
+            .unwrap();
+        }
+
+        let runner = ProjectionRunner::new(pool.clone(), 10);
+        let position = runner
+            .rebuild(
+                "book_projector",
+                DbBookListing::TABLES,
+                |pool| BookProjector::new(DbBookListing::new(pool)),
+                &mut |_| {},
+            )
+            .await
+            .unwrap();
+        slow.rollback().await.unwrap();
+
+        assert_eq!(position.event_id, 2);
+        let books = DbBookListing::new(pool.clone()).list().await;
+        assert_eq!(books[0].name, "book2");
+
+        let subscription = CatchUpSubscription::new(
+            pool,
+            "book_projector",
+            Recorder {
+                events: Arc::new(Mutex::new(Vec::new())),
+            },
+            10,
+        );
+        assert_eq!(subscription.checkpoint().await.unwrap().event_id, 2);
+    }
+
+    #[sqlx::test]
+    async fn catch_up_waits_for_earlier_transactions(pool: PgPool) {
+        seed(&pool).await;
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let subscription = CatchUpSubscription::new(
+            pool.clone(),
+            "recorder",
+            Recorder {
+                events: events.clone(),
+            },
+            10,
+        );
+        subscription.catch_up().await.unwrap();
+
+        let mut slow = pool.begin().await.unwrap();
+        sqlx::query(
+            "insert into stored_event(aggregate_type, aggregate_id, sequence, name, payload) \
+            values ('author', 9, 1, 'author_created', '{}')",
+        )
+        .execute(&mut *slow)
+        .await
+        .unwrap();
+        seed_author(&pool, None).await;
+
+        assert_eq!(subscription.catch_up().await.unwrap().event_id, 2);
+        slow.commit().await.unwrap();
+        subscription.catch_up().await.unwrap();
+
+        assert_eq!(*events.lock().unwrap(), vec![1, 2, 3, 4]);
+    }
+}
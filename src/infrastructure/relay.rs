@@ -0,0 +1,390 @@
+use super::{book_listing::DbBookListing, search::DbSearchIndex, stored_event};
+use crate::application::{
+    book_projector::BookProjector, search_indexer::SearchIndexer, BoxError, EventHandler,
+};
+use async_std::task;
+use sqlx::{postgres::PgRow, PgPool};
+use std::{collections::HashSet, error::Error, fmt, time::Duration};
+
+// Held for the length of every dispatch transaction, so only one relay delivers at a time
+// and others, like a projection swap, can pause delivery by taking it.
+pub(crate) const RELAY_LOCK: i64 = 0x6f7574626f78;
+
+#[derive(Debug)]
+pub enum RelayError {
+    Database(sqlx::Error),
+    Handler {
+        event_id: i32,
+        handler: String,
+        dead_lettered: bool,
+        source: BoxError,
+    },
+}
+
+impl fmt::Display for RelayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelayError::Database(e) => write!(f, "outbox relay database error: {}", e),
+            RelayError::Handler {
+                event_id,
+                handler,
+                dead_lettered,
+                source,
+            } => {
+                write!(
+                    f,
+                    "{} failed on stored_event {}: {}",
+                    handler, event_id, source
+                )?;
+                if *dead_lettered {
+                    write!(f, ", event dead-lettered")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for RelayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RelayError::Database(e) => Some(e),
+            RelayError::Handler { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+impl From<sqlx::Error> for RelayError {
+    fn from(e: sqlx::Error) -> Self {
+        RelayError::Database(e)
+    }
+}
+
+pub struct OutboxRelay {
+    pool: PgPool,
+    batch_size: i64,
+    max_attempts: i32,
+    handlers: Vec<(String, Box<dyn EventHandler>)>,
+}
+
+impl OutboxRelay {
+    pub const DEFAULT_MAX_ATTEMPTS: i32 = 10;
+    const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+    pub fn new(pool: PgPool, batch_size: i64) -> Self {
+        Self {
+            pool,
+            batch_size,
+            max_attempts: Self::DEFAULT_MAX_ATTEMPTS,
+            handlers: Vec::new(),
+        }
+    }
+
+    // The read models every process keeps up to date: the book_listing projection and the
+    // search index.
+    pub fn projections(pool: PgPool, batch_size: i64) -> Self {
+        let mut relay = Self::new(pool.clone(), batch_size);
+        relay.register(
+            "book_projector",
+            BookProjector::new(DbBookListing::new(pool.clone())),
+        );
+        relay.register(
+            "search_indexer",
+            SearchIndexer::new(DbSearchIndex::new(pool)),
+        );
+        relay
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: i32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    // `name` identifies the handler in outbox_delivery, so it must stay the same across
+    // restarts.
+    pub fn register(&mut self, name: &str, handler: impl EventHandler + 'static) {
+        self.handlers.push((String::from(name), Box::new(handler)));
+    }
+
+    // Events are delivered in id order and marked as dispatched once every handler accepted
+    // them. Each handler's delivery is recorded, so after a failure only the handlers that
+    // have not seen the event get it again. A handler's own writes and its delivery record
+    // are not atomic though: a crash between them delivers the event to it again, so
+    // handlers must be idempotent. An event that keeps failing is retried ahead of the
+    // events behind it, to keep their order, until `max_attempts` is reached; it is then
+    // dead-lettered and the relay moves on.
+    pub async fn dispatch(&self) -> Result<usize, RelayError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("select pg_advisory_xact_lock($1)")
+            .bind(RELAY_LOCK)
+            .execute(&mut *tx)
+            .await?;
+
+        let events = sqlx::query(
+            "select * from stored_event where dispatched_at is null and dead_lettered_at is null \
+            order by id limit $1 for update",
+        )
+        .bind(self.batch_size)
+        .map(|row: PgRow| stored_event(&row))
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut dispatched = 0;
+        let mut failure = None;
+        'events: for event in events {
+            let delivered: HashSet<String> = sqlx::query_as::<_, (String,)>(
+                "select handler from outbox_delivery where event_id = $1",
+            )
+            .bind(event.id())
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .map(|(handler,)| handler)
+            .collect();
+
+            for (name, handler) in &self.handlers {
+                if delivered.contains(name) {
+                    continue;
+                }
+                if let Err(source) = handler.handle(&event).await {
+                    let (dead_lettered,): (bool,) = sqlx::query_as(
+                        "update stored_event set dispatch_attempts = dispatch_attempts + 1, dispatch_error = $2, \
+                        dead_lettered_at = case when dispatch_attempts + 1 >= $3 then now() end \
+                        where id = $1 returning dead_lettered_at is not null",
+                    )
+                    .bind(event.id())
+                    .bind(source.to_string())
+                    .bind(self.max_attempts)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                    failure = Some(RelayError::Handler {
+                        event_id: event.id(),
+                        handler: name.clone(),
+                        dead_lettered,
+                        source,
+                    });
+                    break 'events;
+                }
+                sqlx::query("insert into outbox_delivery(event_id, handler) values ($1, $2)")
+                    .bind(event.id())
+                    .bind(name)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            sqlx::query("update stored_event set dispatched_at = now() where id = $1")
+                .bind(event.id())
+                .execute(&mut *tx)
+                .await?;
+            dispatched += 1;
+        }
+
+        tx.commit().await?;
+
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(dispatched),
+        }
+    }
+
+    // Dispatches until the outbox is empty, for processes that do not keep a relay running.
+    pub async fn drain(&self) -> Result<usize, RelayError> {
+        let mut drained = 0;
+        loop {
+            let dispatched = self.dispatch().await?;
+            drained += dispatched;
+            if (dispatched as i64) < self.batch_size {
+                return Ok(drained);
+            }
+        }
+    }
+
+    // Puts a dead-lettered event back in the outbox with a fresh attempt count.
+    pub async fn requeue(&self, event_id: i32) -> Result<bool, RelayError> {
+        let result = sqlx::query(
+            "update stored_event set dead_lettered_at = null, dispatch_attempts = 0, dispatch_error = null \
+            where id = $1 and dead_lettered_at is not null",
+        )
+        .bind(event_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    // Consecutive failures double the wait between runs, up to MAX_BACKOFF.
+    pub async fn run(&self, interval: Duration) {
+        let mut failures: u32 = 0;
+        loop {
+            match self.dispatch().await {
+                Ok(dispatched) => {
+                    failures = 0;
+                    if dispatched as i64 == self.batch_size {
+                        continue;
+                    }
+                }
+                Err(e) => {
+                    failures += 1;
+                    log::error!("{}", e);
+                }
+            }
+            let backoff = interval.saturating_mul(1 << failures.min(16));
+            task::sleep(backoff.min(Self::MAX_BACKOFF.max(interval))).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        application::{EventStore, StoredEvent, UoW},
+        domain::{author::Author, DomainEventPublisher},
+        infrastructure::{DbEventStore, DbUoW},
+    };
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<i32>>>,
+        // Fails on this aggregate for the given number of deliveries.
+        fail_on: Option<(i32, Arc<Mutex<usize>>)>,
+    }
+
+    impl Recorder {
+        fn failing(aggregate_id: i32, times: usize) -> Self {
+            Self {
+                events: Arc::default(),
+                fail_on: Some((aggregate_id, Arc::new(Mutex::new(times)))),
+            }
+        }
+
+        fn events(&self) -> Vec<i32> {
+            self.events.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl EventHandler for Recorder {
+        async fn handle(&self, event: &StoredEvent) -> Result<(), BoxError> {
+            if let Some((id, remaining)) = &self.fail_on {
+                let mut remaining = remaining.lock().unwrap();
+                if *id == event.aggregate_id() && *remaining > 0 {
+                    *remaining -= 1;
+                    return Err("handler failed".into());
+                }
+            }
+            self.events.lock().unwrap().push(event.aggregate_id());
+            Ok(())
+        }
+    }
+
+    async fn create_authors(pool: &PgPool, ids: &[i32]) {
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        {
+            let publisher = DomainEventPublisher::new();
+            publisher.subscribe(|e| event_store.append(e));
+            for id in ids {
+                Author::new(*id, "f", "l", &publisher).unwrap();
+            }
+        }
+        uow.commit().await.unwrap();
+    }
+
+    #[sqlx::test]
+    async fn dispatch(pool: PgPool) {
+        create_authors(&pool, &[1, 2, 3]).await;
+        let recorder = Recorder::default();
+
+        let mut relay = OutboxRelay::new(pool.clone(), 2);
+        relay.register("recorder", recorder.clone());
+
+        assert_eq!(relay.dispatch().await.unwrap(), 2);
+        assert_eq!(relay.dispatch().await.unwrap(), 1);
+        assert_eq!(relay.dispatch().await.unwrap(), 0);
+        assert_eq!(recorder.events(), vec![1, 2, 3]);
+    }
+
+    #[sqlx::test]
+    async fn drain(pool: PgPool) {
+        create_authors(&pool, &[1, 2, 3, 4]).await;
+        let recorder = Recorder::default();
+
+        let mut relay = OutboxRelay::new(pool.clone(), 2);
+        relay.register("recorder", recorder.clone());
+
+        assert_eq!(relay.drain().await.unwrap(), 4);
+        assert_eq!(relay.drain().await.unwrap(), 0);
+        assert_eq!(recorder.events(), vec![1, 2, 3, 4]);
+    }
+
+    #[sqlx::test]
+    async fn redeliver_only_to_failed_handlers(pool: PgPool) {
+        create_authors(&pool, &[1, 2]).await;
+        let first = Recorder::default();
+        let second = Recorder::failing(2, 1);
+
+        let mut relay = OutboxRelay::new(pool.clone(), 10);
+        relay.register("first", first.clone());
+        relay.register("second", second.clone());
+
+        let result = relay.dispatch().await;
+        assert!(matches!(
+            result,
+            Err(RelayError::Handler {
+                event_id: 2,
+                dead_lettered: false,
+                ..
+            })
+        ));
+        assert_eq!(first.events(), vec![1, 2]);
+        assert_eq!(second.events(), vec![1]);
+
+        let mut restarted = OutboxRelay::new(pool, 10);
+        restarted.register("first", first.clone());
+        restarted.register("second", second.clone());
+
+        assert_eq!(restarted.dispatch().await.unwrap(), 1);
+        assert_eq!(first.events(), vec![1, 2]);
+        assert_eq!(second.events(), vec![1, 2]);
+    }
+
+    #[sqlx::test]
+    async fn dead_letter_after_max_attempts(pool: PgPool) {
+        create_authors(&pool, &[1, 2]).await;
+        let recorder = Recorder::failing(1, usize::MAX);
+
+        let mut relay = OutboxRelay::new(pool.clone(), 10).with_max_attempts(2);
+        relay.register("recorder", recorder.clone());
+
+        assert!(matches!(
+            relay.dispatch().await,
+            Err(RelayError::Handler {
+                dead_lettered: false,
+                ..
+            })
+        ));
+        assert!(matches!(
+            relay.dispatch().await,
+            Err(RelayError::Handler {
+                dead_lettered: true,
+                ..
+            })
+        ));
+        assert_eq!(relay.dispatch().await.unwrap(), 1);
+        assert_eq!(recorder.events(), vec![2]);
+
+        let (attempts, error): (i32, String) = sqlx::query_as(
+            "select dispatch_attempts, dispatch_error from stored_event where id = 1",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!((attempts, error.as_str()), (2, "handler failed"));
+
+        assert!(relay.requeue(1).await.unwrap());
+        assert!(!relay.requeue(2).await.unwrap());
+        assert!(relay.dispatch().await.is_err());
+    }
+}
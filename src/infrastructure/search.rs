@@ -0,0 +1,161 @@
+use crate::application::{
+    search::{SearchHit, SearchIndex, SearchKind},
+    BoxError,
+};
+use async_trait::async_trait;
+use sqlx::{postgres::PgRow, PgPool, Row};
+
+pub struct DbSearchIndex {
+    pool: PgPool,
+}
+
+impl DbSearchIndex {
+    pub const TABLES: &'static [&'static str] = &["search_document"];
+
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl SearchIndex for DbSearchIndex {
+    async fn index(&self, kind: SearchKind, id: i32, title: &str) -> Result<(), BoxError> {
+        sqlx::query(
+            "insert into search_document(kind, id, title, document) \
+            values ($1, $2, $3, to_tsvector('english', $3)) \
+            on conflict (kind, id) do update set title = excluded.title, document = excluded.document",
+        )
+        .bind(kind.name())
+        .bind(id)
+        .bind(title)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Every term is matched as a stemmed prefix, so "prat" finds "Pratchett" and "omen"
+    // finds "Omens".
+    async fn search(&self, terms: &[String], limit: i64) -> Vec<SearchHit> {
+        let query: Vec<String> = terms.iter().map(|t| format!("{}:*", t)).collect();
+
+        sqlx::query(
+            "select kind, id, title, \
+                ts_headline('english', title, q, 'StartSel=<b>, StopSel=</b>, HighlightAll=true') as snippet, \
+                ts_rank(document, q) as rank \
+            from search_document, to_tsquery('english', $1) q \
+            where document @@ q \
+            order by rank desc, kind, id \
+            limit $2",
+        )
+        .bind(query.join(" & "))
+        .bind(limit)
+        .map(|row: PgRow| SearchHit {
+            kind: match row.get("kind") {
+                "author" => SearchKind::Author,
+                _ => SearchKind::Book,
+            },
+            id: row.get("id"),
+            title: row.get("title"),
+            snippet: row.get("snippet"),
+            rank: row.get("rank"),
+        })
+        .fetch_all(&self.pool)
+        .await
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        application::{self, search, search_indexer::SearchIndexer},
+        domain::DomainEventPublisher,
+        infrastructure::{
+            author::DbAuthorRepository, book::DbBookRepository, relay::OutboxRelay, DbEventStore,
+            DbUoW,
+        },
+    };
+
+    #[sqlx::test]
+    async fn search(pool: PgPool) {
+        let uow = DbUoW::new(pool.clone());
+        let mut event_store = DbEventStore::new(&uow);
+        {
+            let publisher = DomainEventPublisher::new();
+            let authors = DbAuthorRepository::new(&uow, &publisher);
+            application::author::create(
+                "Terry",
+                "Pratchett",
+                &publisher,
+                &authors,
+                &mut event_store,
+                &uow,
+            )
+            .await
+            .unwrap();
+        }
+        {
+            let publisher = DomainEventPublisher::new();
+            let books = DbBookRepository::new(&uow, &publisher);
+            let authors = DbAuthorRepository::new(&uow, &publisher);
+            application::book::create(
+                "Running Wizards",
+                300,
+                vec![1],
+                None,
+                &publisher,
+                &books,
+                &authors,
+                &mut event_store,
+                &uow,
+            )
+            .await
+            .unwrap();
+        }
+        {
+            let publisher = DomainEventPublisher::new();
+            let books = DbBookRepository::new(&uow, &publisher);
+            let authors = DbAuthorRepository::new(&uow, &publisher);
+            application::book::update(
+                1,
+                "The Running Wizard",
+                300,
+                vec![1],
+                None,
+                &publisher,
+                &books,
+                &authors,
+                &mut event_store,
+                &uow,
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut relay = OutboxRelay::new(pool.clone(), 10);
+        relay.register(
+            "search_indexer",
+            SearchIndexer::new(DbSearchIndex::new(pool.clone())),
+        );
+        relay.dispatch().await.unwrap();
+
+        let index = DbSearchIndex::new(pool);
+
+        let hits = search::search("wizards run", 10, &index).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].kind, SearchKind::Book);
+        assert_eq!(hits[0].title, "The Running Wizard");
+        assert_eq!(hits[0].snippet, "The <b>Running</b> <b>Wizard</b>");
+
+        let hits = search::search("prat", 10, &index).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].kind, SearchKind::Author);
+        assert_eq!(hits[0].snippet, "Terry <b>Pratchett</b>");
+
+        assert!(search::search("dragons", 10, &index)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,19 +1,81 @@
-// fn main() {
-//     let mut s = String::from("hello");
-//     let mut x = X { s: &mut s };
+mod cli;
+mod server;
 
-//     s.push('!');
-//     x.push();
-// }
+use async_std::task;
+use books::infrastructure::relay::OutboxRelay;
+use clap::Parser;
+use cli::{Cli, Command};
+use sqlx::PgPool;
+use std::{env, process::ExitCode, time::Duration};
 
-// struct X<'a> {
-//     s: &'a mut String,
-// }
+#[async_std::main]
+async fn main() -> ExitCode {
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
 
-// impl<'a> X<'a> {
-//     fn push(&mut self) {
-//         self.s.push('!');
-//     }
-// }
+    let database_url = match env::var("DATABASE_URL") {
+        Ok(url) => url,
+        Err(e) => {
+            eprintln!("DATABASE_URL: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let pool = match PgPool::connect(&database_url).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let result = match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            return match serve(pool).await {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+        Command::Migrate => cli::migrate(pool).await.map(print),
+        Command::Author(command) => cli::author(command, cli.format, pool).await.map(print),
+        Command::Book(command) => cli::book(command, cli.format, pool).await.map(print),
+        Command::Events(command) => cli::events(command, cli.format, pool).await,
+        Command::Export(command) => cli::export(command, pool).await,
+        Command::Projection(command) => cli::projection(command, pool).await.map(print),
+        Command::Import(command) => match cli::import(command, cli.format, pool).await {
+            Ok(out) => {
+                print(out);
+                Ok(())
+            }
+            Err((out, e)) => {
+                print(out);
+                Err(e)
+            }
+        },
+    };
 
-fn main() {}
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            e.exit_code()
+        }
+    }
+}
+
+fn print(out: String) {
+    print!("{}", out);
+}
+
+async fn serve(pool: PgPool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let address = env::var("LISTEN_ADDRESS").unwrap_or_else(|_| String::from("127.0.0.1:8080"));
+    tide::log::start();
+    sqlx::migrate!().run(&pool).await?;
+
+    let relay = OutboxRelay::projections(pool.clone(), 100);
+    task::spawn(async move { relay.run(Duration::from_secs(1)).await });
+
+    server::app(pool).listen(address).await?;
+    Ok(())
+}
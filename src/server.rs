@@ -0,0 +1,380 @@
+use books::{
+    application::{self, author_listing, book_listing, catalog, search, ApplicationError},
+    domain::DomainEventPublisher,
+    infrastructure::{
+        author::DbAuthorRepository, author_listing::DbAuthorListing, book::DbBookRepository,
+        book_listing::DbBookListing, catalog::DbCatalog, search::DbSearchIndex, DbEventStore,
+        DbUoW,
+    },
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+use tide::{Request, Response, StatusCode};
+
+pub fn app(pool: PgPool) -> tide::Server<PgPool> {
+    let mut app = tide::with_state(pool);
+    app.at("/authors").get(list_authors).post(create_author);
+    app.at("/authors/:id").get(get_author).put(update_author);
+    app.at("/books").get(list_books).post(create_book);
+    app.at("/books/:id").get(get_book).put(update_book);
+    app.at("/search").get(search);
+    app
+}
+
+#[derive(Deserialize)]
+struct AuthorBody {
+    first_name: String,
+    last_name: String,
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_limit() -> i64 {
+    20
+}
+
+// A missing isbn leaves it unchanged on update, while null clears it.
+#[derive(Deserialize)]
+struct BookBody {
+    name: String,
+    pages_count: i32,
+    authors: Vec<i32>,
+    #[serde(default, deserialize_with = "present")]
+    isbn: Option<Option<String>>,
+}
+
+fn present<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Option<String>>, D::Error> {
+    Option::deserialize(d).map(Some)
+}
+
+async fn create_author(mut req: Request<PgPool>) -> tide::Result {
+    let body: AuthorBody = req.body_json().await?;
+
+    let uow = DbUoW::new(req.state().clone());
+    let mut event_store = DbEventStore::new(&uow);
+    let publisher = DomainEventPublisher::new();
+    let repo = DbAuthorRepository::new(&uow, &publisher);
+
+    let result = application::author::create(
+        &body.first_name,
+        &body.last_name,
+        &publisher,
+        &repo,
+        &mut event_store,
+        &uow,
+    )
+    .await;
+    created(result, "authors")
+}
+
+async fn update_author(mut req: Request<PgPool>) -> tide::Result {
+    let id = id(&req)?;
+    let body: AuthorBody = req.body_json().await?;
+
+    let uow = DbUoW::new(req.state().clone());
+    let mut event_store = DbEventStore::new(&uow);
+    let publisher = DomainEventPublisher::new();
+    let repo = DbAuthorRepository::new(&uow, &publisher);
+
+    let result = application::author::update(
+        id,
+        &body.first_name,
+        &body.last_name,
+        &publisher,
+        &repo,
+        &mut event_store,
+        &uow,
+    )
+    .await;
+    no_content(result)
+}
+
+async fn get_author(req: Request<PgPool>) -> tide::Result {
+    let listing = DbAuthorListing::new(req.state().clone());
+    ok(author_listing::get(id(&req)?, &listing).await)
+}
+
+async fn list_authors(req: Request<PgPool>) -> tide::Result {
+    let listing = DbAuthorListing::new(req.state().clone());
+    ok(Ok(author_listing::list(&listing).await))
+}
+
+async fn create_book(mut req: Request<PgPool>) -> tide::Result {
+    let body: BookBody = req.body_json().await?;
+
+    let uow = DbUoW::new(req.state().clone());
+    let mut event_store = DbEventStore::new(&uow);
+    let publisher = DomainEventPublisher::new();
+    let repo = DbBookRepository::new(&uow, &publisher);
+    let authors = DbAuthorRepository::new(&uow, &publisher);
+
+    let result = application::book::create(
+        &body.name,
+        body.pages_count,
+        body.authors,
+        body.isbn.flatten().as_deref(),
+        &publisher,
+        &repo,
+        &authors,
+        &mut event_store,
+        &uow,
+    )
+    .await;
+    created(result, "books")
+}
+
+async fn update_book(mut req: Request<PgPool>) -> tide::Result {
+    let id = id(&req)?;
+    let body: BookBody = req.body_json().await?;
+
+    let uow = DbUoW::new(req.state().clone());
+    let mut event_store = DbEventStore::new(&uow);
+    let publisher = DomainEventPublisher::new();
+    let repo = DbBookRepository::new(&uow, &publisher);
+    let authors = DbAuthorRepository::new(&uow, &publisher);
+
+    let result = application::book::update(
+        id,
+        &body.name,
+        body.pages_count,
+        body.authors,
+        body.isbn.as_ref().map(Option::as_deref),
+        &publisher,
+        &repo,
+        &authors,
+        &mut event_store,
+        &uow,
+    )
+    .await;
+    no_content(result)
+}
+
+// Read from the book table rather than the book_listing projection, so the Location of a
+// created book can be followed right away.
+async fn get_book(req: Request<PgPool>) -> tide::Result {
+    let catalog = DbCatalog::new(req.state().clone());
+    ok(catalog::book(id(&req)?, &catalog).await)
+}
+
+async fn list_books(req: Request<PgPool>) -> tide::Result {
+    let listing = DbBookListing::new(req.state().clone());
+    ok(Ok(book_listing::list(&listing).await))
+}
+
+async fn search(req: Request<PgPool>) -> tide::Result {
+    let params: SearchParams = req.query()?;
+    let index = DbSearchIndex::new(req.state().clone());
+    ok(search::search(&params.q, params.limit, &index).await)
+}
+
+fn id(req: &Request<PgPool>) -> tide::Result<i32> {
+    req.param("id")?
+        .parse()
+        .map_err(|e| tide::Error::new(StatusCode::BadRequest, e))
+}
+
+fn ok(result: Result<impl Serialize, ApplicationError>) -> tide::Result {
+    match result {
+        Ok(body) => json(StatusCode::Ok, body),
+        Err(e) => error(e),
+    }
+}
+
+fn created(result: Result<i32, ApplicationError>, collection: &str) -> tide::Result {
+    match result {
+        Ok(id) => {
+            let mut response = json(StatusCode::Created, json!({ "id": id }))?;
+            response.insert_header("Location", format!("/{}/{}", collection, id));
+            Ok(response)
+        }
+        Err(e) => error(e),
+    }
+}
+
+fn no_content(result: Result<(), ApplicationError>) -> tide::Result {
+    match result {
+        Ok(()) => Ok(Response::new(StatusCode::NoContent)),
+        Err(e) => error(e),
+    }
+}
+
+fn error(e: ApplicationError) -> tide::Result {
+    let (status, errors) = match &e {
+        ApplicationError::NotFound(_) => (StatusCode::NotFound, vec![e.to_string()]),
+        ApplicationError::Domain(errors) => (
+            StatusCode::UnprocessableEntity,
+            errors.iter().map(|e| e.to_string()).collect(),
+        ),
+        ApplicationError::InvalidQuery(_) => (StatusCode::BadRequest, vec![e.to_string()]),
+        ApplicationError::Conflict(_) => (StatusCode::Conflict, vec![e.to_string()]),
+        ApplicationError::Handler(_)
+        | ApplicationError::Commit(_)
+        | ApplicationError::Repository(_) => {
+            log::error!("{}", e);
+            (
+                StatusCode::InternalServerError,
+                vec![String::from("internal error")],
+            )
+        }
+    };
+    json(status, json!({ "errors": errors }))
+}
+
+fn json(status: StatusCode, body: impl Serialize) -> tide::Result {
+    let mut response = Response::new(status);
+    response.set_body(tide::Body::from_json(&body)?);
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest, Url};
+
+    async fn send(
+        app: &tide::Server<PgPool>,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut req = HttpRequest::new(
+            method,
+            Url::parse("http://localhost").unwrap().join(path).unwrap(),
+        );
+        if let Some(body) = body {
+            req.set_body(tide::Body::from_json(&body).unwrap());
+        }
+        let mut res: tide::http::Response = app.respond(req).await.unwrap();
+        let body = res.body_string().await.unwrap();
+        (
+            res.status(),
+            serde_json::from_str(&body).unwrap_or(serde_json::Value::Null),
+        )
+    }
+
+    #[sqlx::test]
+    async fn authors(pool: PgPool) {
+        let app = app(pool);
+
+        let (status, body) = send(
+            &app,
+            Method::Post,
+            "/authors",
+            Some(json!({ "first_name": "f1", "last_name": "l1" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::Created);
+        let id = body["id"].as_i64().unwrap();
+
+        let (status, _) = send(
+            &app,
+            Method::Put,
+            &format!("/authors/{}", id),
+            Some(json!({ "first_name": "f2", "last_name": "l1" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NoContent);
+
+        let (status, body) = send(&app, Method::Get, &format!("/authors/{}", id), None).await;
+        assert_eq!(status, StatusCode::Ok);
+        assert_eq!(body["full_name"], "f2 l1");
+
+        let (status, body) = send(
+            &app,
+            Method::Post,
+            "/authors",
+            Some(json!({ "first_name": "", "last_name": "" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UnprocessableEntity);
+        assert_eq!(body["errors"].as_array().unwrap().len(), 2);
+
+        let (status, _) = send(&app, Method::Get, "/authors/42", None).await;
+        assert_eq!(status, StatusCode::NotFound);
+    }
+
+    #[sqlx::test]
+    async fn books(pool: PgPool) {
+        let app = app(pool);
+        send(
+            &app,
+            Method::Post,
+            "/authors",
+            Some(json!({ "first_name": "f1", "last_name": "l1" })),
+        )
+        .await;
+
+        let (status, body) = send(
+            &app,
+            Method::Post,
+            "/books",
+            Some(json!({
+                "name": "book1",
+                "pages_count": 100,
+                "authors": [1],
+                "isbn": "0-306-40615-2"
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::Created);
+        let id = body["id"].as_i64().unwrap();
+
+        let (status, body) = send(&app, Method::Get, &format!("/books/{}", id), None).await;
+        assert_eq!(status, StatusCode::Ok);
+        assert_eq!(body["name"], "book1");
+
+        let (status, _) = send(
+            &app,
+            Method::Put,
+            &format!("/books/{}", id),
+            Some(json!({ "name": "book2", "pages_count": 120, "authors": [1] })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NoContent);
+
+        let (status, body) = send(&app, Method::Get, &format!("/books/{}", id), None).await;
+        assert_eq!(status, StatusCode::Ok);
+        assert_eq!(body["name"], "book2");
+        assert_eq!(body["pages_count"], 120);
+        assert_eq!(body["isbn"], "9780306406157");
+
+        let (status, _) = send(
+            &app,
+            Method::Put,
+            &format!("/books/{}", id),
+            Some(json!({ "name": "book2", "pages_count": 120, "authors": [1], "isbn": null })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NoContent);
+        let (_, body) = send(&app, Method::Get, &format!("/books/{}", id), None).await;
+        assert_eq!(body["isbn"], serde_json::Value::Null);
+
+        let (status, body) = send(
+            &app,
+            Method::Post,
+            "/books",
+            Some(json!({ "name": "", "pages_count": 0, "authors": [1] })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UnprocessableEntity);
+        assert_eq!(body["errors"].as_array().unwrap().len(), 2);
+
+        let (status, _) = send(&app, Method::Get, "/books/42", None).await;
+        assert_eq!(status, StatusCode::NotFound);
+
+        let (status, _) = send(
+            &app,
+            Method::Put,
+            "/books/42",
+            Some(json!({ "name": "book3", "pages_count": 1, "authors": [1] })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NotFound);
+    }
+}